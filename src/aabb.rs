@@ -1,7 +1,7 @@
 use crate::scene::SceneItem;
 use crate::vector::Vec3;
 use crate::ray::Ray;
-use crate::intersectable::{Intersectable, Intersection};
+use crate::intersectable::{Intersection, closest_hit};
 use rand::Rng;
 
 use std::f64;
@@ -23,6 +23,7 @@ impl AABB {
 
     pub fn max(&self) -> Vec3 { self.max }
 
+    #[allow(clippy::needless_return)]
     pub fn intersect(&self, ray: &Ray) -> bool {
         let invn_dir_x = 1. / ray.direction().x();
         let invn_dir_y = 1. / ray.direction().y();
@@ -68,6 +69,29 @@ impl AABB {
 
         return true
     }
+
+    pub fn contains(&self, p: &Vec3, tolerance: f64) -> bool {
+        (0..3).all(|axis| {
+            let x = p.get_ind(axis);
+            x >= self.min.get_ind(axis) - tolerance && x <= self.max.get_ind(axis) + tolerance
+        })
+    }
+
+    // ray parameters where the ray enters and leaves the box
+    pub fn clip(&self, ray: &Ray) -> Option<(f64, f64)> {
+        let (mut t_near, mut t_far) = (f64::MIN, f64::MAX);
+        for axis in 0..3 {
+            let inv = 1. / ray.direction().get_ind(axis);
+            let mut t0 = (self.min.get_ind(axis) - ray.origin().get_ind(axis)) * inv;
+            let mut t1 = (self.max.get_ind(axis) - ray.origin().get_ind(axis)) * inv;
+            if inv < 0. {
+                mem::swap(&mut t0, &mut t1);
+            }
+            t_near = t_near.max(t0);
+            t_far = t_far.min(t1);
+        }
+        if t_near > t_far { None } else { Some((t_near, t_far)) }
+    }
 }
 
 pub trait BoundingBox {
@@ -89,7 +113,7 @@ pub fn surrounding_box(box0: &AABB, box1: &AABB) -> AABB {
     AABB::new(small, big)
 }
 
-
+#[derive(Clone, Debug)]
 pub struct BVH {
     // can has (left AND right) OR item
     pub bbox: AABB,
@@ -99,6 +123,7 @@ pub struct BVH {
 }
 
 impl BVH {
+    #[allow(clippy::needless_return)]
     pub fn new(items: &mut [SceneItem]) -> BVH {
         let axis_ind = (3. * rand::thread_rng().gen::<f32>()) as u8;
         if axis_ind == 0 {
@@ -113,11 +138,11 @@ impl BVH {
             return BVH {
                 left: None,
                 right: None,
-                item: Some(items[0]),
+                item: Some(items[0].clone()),
                 bbox: items[0].bounding_box()
             }
         } else {
-            let middle = (items.len() / 2) as usize;
+            let middle = items.len() / 2;
 
             let left = BVH::new(&mut items[0..middle]);
             let right = BVH::new(&mut items[middle..]);
@@ -137,34 +162,25 @@ impl BVH {
 
     pub fn right(&self) -> Option<&BVH> { self.right.as_deref() }
 
-    pub fn item(&self) -> Option<SceneItem> { self.item }
+    pub fn item(&self) -> Option<&SceneItem> { self.item.as_ref() }
 
-    pub fn intersect(&self, ray: &Ray) -> Option<Intersection> {
+    #[allow(clippy::needless_return)]
+    pub fn intersect(&self, ray: &Ray, t_min: f64) -> Option<Intersection<'_>> {
         match self.item {
-            Some(item) => {
-                let point = item.intersect(&ray);
-                if point > 0. {
-                    return Some(Intersection {
-                        intersected: item,
-                        dist: point
-                    })
-                } else {
-                    return None;
-                }
-            },
+            Some(ref item) => closest_hit(item, ray, t_min),
             None => {
-                if self.bbox.intersect(&ray) {
+                if self.bbox.intersect(ray) {
                     let l = self.left().unwrap();
                     let r = self.right().unwrap();
 
-                    let intersect_left = l.intersect(&ray);
-                    let intersect_right = r.intersect(&ray);
+                    let intersect_left = l.intersect(ray, t_min);
+                    let intersect_right = r.intersect(ray, t_min);
 
                     match intersect_left {
                         Some(i_left) => {
                             match intersect_right {
                                 Some(i_right) => {
-                                    if i_left.dist < i_right.dist {
+                                    if i_left.hit.dist < i_right.hit.dist {
                                         return Some(i_left);
                                     } else {
                                         return Some(i_right);
@@ -203,7 +219,7 @@ impl BoundingBox for BVH {
             },
             None => {
                 match self.item {
-                    Some(item) => item.bounding_box(),
+                    Some(ref item) => item.bounding_box(),
                     // if BVH not has leafs and not has item
                     None => panic!("Empty BVH")
                 }
@@ -211,3 +227,37 @@ impl BoundingBox for BVH {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::{Sphere, Material, Coloration};
+    use crate::texture::{CheckerTexture, CheckerMode};
+    use crate::color::Color;
+    use crate::intersectable::T_MIN;
+
+    #[test]
+    fn cut_out_hits_are_skipped() {
+        // solid checker with unit cells, black (cut out) on even cells: the near side of the
+        // front sphere lies in cell (0, 0, 0), its far side in (0, 0, -1)
+        let checker = CheckerTexture::with_settings(Color::white(), Color::black(), 1., Vec3::zero(), CheckerMode::Solid);
+        let holed = Material { opacity: Some(Coloration::CheckerTexture(checker)), ..Material::default() };
+        let clear = Material { opacity: Some(Coloration::Color(Color::black())), ..Material::default() };
+        let mut items = vec![
+            SceneItem::Sphere(Sphere::new(Vec3::new(0., 0., 0.), 0.9, holed)),
+            SceneItem::Sphere(Sphere::new(Vec3::new(0., 0., 3.), 1.2, clear)),
+            SceneItem::Sphere(Sphere::new(Vec3::new(0., 0., -5.), 1., Material::default()))
+        ];
+        let bvh = BVH::new(&mut items);
+
+        let ray = Ray::new(Vec3::new(0.3, 0.2, 10.), Vec3::new(0., 0., -1.), 0.);
+        let hit = bvh.intersect(&ray, T_MIN).expect("the back face of the holed sphere");
+        let z = ray.point_at(hit.hit.dist).z();
+        assert!((z + (0.81f64 - 0.13).sqrt()).abs() < 1e-9, "hit at z = {}", z);
+
+        // rays missing the holed sphere pass the clear one and reach the opaque one
+        let ray = Ray::new(Vec3::new(0.1, 0.95, 10.), Vec3::new(0., 0., -1.), 0.);
+        let hit = bvh.intersect(&ray, T_MIN).expect("the opaque sphere");
+        assert!((ray.point_at(hit.hit.dist).z() + 5. - (1f64 - 0.9125).sqrt()).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,310 @@
+use std::f32::consts::PI;
+
+use crate::vector::Vec3;
+use crate::color::Color;
+use crate::spectrum::{Spectrum, Wavelengths};
+
+// all directions are in a local frame, anisotropic GGX uses z as the normal,
+// fibers use x along the fiber and z as the normal
+
+// GGX visible normal sampling (Heitz 2018), `ax` and `ay` are the roughness along x and y
+pub fn sample_ggx_visible(wo: Vec3, ax: f64, ay: f64, u1: f64, u2: f64) -> Vec3 {
+    // stretch to the hemisphere configuration
+    let vh = Vec3::new(ax * wo.x(), ay * wo.y(), wo.z()).normalize();
+    let len_sq = vh.x() * vh.x() + vh.y() * vh.y();
+    let t1 = if len_sq > 0. {
+        Vec3::new(-vh.y(), vh.x(), 0.) / len_sq.sqrt()
+    } else {
+        Vec3::new(1., 0., 0.)
+    };
+    let t2 = vh.cross(&t1);
+
+    let r = u1.sqrt();
+    let phi = 2. * std::f64::consts::PI * u2;
+    let p1 = r * phi.cos();
+    let s = 0.5 * (1. + vh.z());
+    let p2 = (1. - s) * (1. - p1 * p1).max(0.).sqrt() + s * r * phi.sin();
+    let nh = p1 * t1 + p2 * t2 + (1. - p1 * p1 - p2 * p2).max(0.).sqrt() * vh;
+
+    Vec3::new(ax * nh.x(), ay * nh.y(), nh.z().max(1e-6)).normalize()
+}
+
+fn ggx_lambda(w: Vec3, ax: f64, ay: f64) -> f64 {
+    let tan_sq = (ax * ax * w.x() * w.x() + ay * ay * w.y() * w.y()) / (w.z() * w.z());
+    0.5 * (-1. + (1. + tan_sq).sqrt())
+}
+
+// weight of a reflection sampled from the visible normals, G2 / G1
+pub fn ggx_reflection_weight(wo: Vec3, wi: Vec3, ax: f64, ay: f64) -> f64 {
+    let lambda_o = ggx_lambda(wo, ax, ay);
+    let lambda_i = ggx_lambda(wi, ax, ay);
+    (1. + lambda_o) / (1. + lambda_o + lambda_i)
+}
+
+// absorption coefficient of hair from its pigment concentrations (d'Eon et al. 2011)
+pub fn melanin_absorption(eumelanin: f32, pheomelanin: f32) -> Color {
+    Color::new(
+        eumelanin * 0.419 + pheomelanin * 0.187,
+        eumelanin * 0.697 + pheomelanin * 0.4,
+        eumelanin * 1.37 + pheomelanin * 1.05)
+}
+
+// number of explicit lobes, R, TT and TRT, the rest is lumped into one
+const P_MAX: usize = 3;
+
+// hair scattering after Marschner et al. with the energy conserving lobes of d'Eon et al.,
+// follows the formulation of pbrt
+pub struct Fiber {
+    // offset across the fiber in [-1, 1]
+    h: f32,
+    gamma_o: f32,
+    eta: f32,
+    sigma_a: Spectrum,
+    v: [f32; P_MAX + 1],
+    s: f32,
+    sin_2k_alpha: [f32; 3],
+    cos_2k_alpha: [f32; 3]
+}
+
+impl Fiber {
+    // `beta_m` and `beta_n` are longitudinal and azimuthal roughness in [0, 1], `alpha` the scale tilt in degrees
+    pub fn new(h: f32, eta: f32, sigma_a: Spectrum, beta_m: f32, beta_n: f32, alpha: f32) -> Fiber {
+        let h = h.clamp(-1., 1.);
+        let v0 = (0.726 * beta_m + 0.812 * beta_m * beta_m + 3.7 * beta_m.powi(20)).powi(2).max(1e-4);
+        let s = (PI / 8.).sqrt() * (0.265 * beta_n + 1.194 * beta_n * beta_n + 5.372 * beta_n.powi(22)).max(1e-3);
+
+        let mut sin_2k_alpha = [alpha.to_radians().sin(); 3];
+        let mut cos_2k_alpha = [(1. - sin_2k_alpha[0] * sin_2k_alpha[0]).max(0.).sqrt(); 3];
+        for i in 1..3 {
+            sin_2k_alpha[i] = 2. * cos_2k_alpha[i - 1] * sin_2k_alpha[i - 1];
+            cos_2k_alpha[i] = cos_2k_alpha[i - 1] * cos_2k_alpha[i - 1] - sin_2k_alpha[i - 1] * sin_2k_alpha[i - 1];
+        }
+
+        Fiber {
+            h,
+            gamma_o: h.asin(),
+            eta,
+            sigma_a,
+            v: [v0, 0.25 * v0, 4. * v0, 4. * v0],
+            s,
+            sin_2k_alpha,
+            cos_2k_alpha
+        }
+    }
+
+    // samples an incoming direction for `wo`, returns it with f * |cos| / pdf
+    pub fn sample(&self, wo: Vec3, wavelengths: &Wavelengths, u: [f32; 4]) -> Option<(Vec3, Spectrum)> {
+        let sin_theta_o = wo.x() as f32;
+        let cos_theta_o = (1. - sin_theta_o * sin_theta_o).max(0.).sqrt();
+        let phi_o = (wo.z() as f32).atan2(wo.y() as f32);
+        let (ap, gamma_t) = self.attenuation(sin_theta_o, cos_theta_o);
+
+        // pick a lobe proportional to its attenuation
+        let ap_average: Vec<f32> = ap.iter().map(|a| wavelengths.average(a)).collect();
+        let total: f32 = ap_average.iter().sum();
+        if total <= 0. {
+            return None;
+        }
+        let mut u0 = u[0] * total;
+        let mut p = P_MAX;
+        for (i, a) in ap_average.iter().enumerate().take(P_MAX) {
+            if u0 < *a {
+                p = i;
+                break;
+            }
+            u0 -= a;
+        }
+
+        // longitudinal angle around the tilted specular cone
+        let (sin_theta_op, cos_theta_op) = self.tilt(p, sin_theta_o, cos_theta_o);
+        let v = self.v[p];
+        let u1 = u[1].max(1e-5);
+        let cos_theta = 1. + v * (u1 + (1. - u1) * (-2. / v).exp()).ln();
+        let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+        let cos_phi = (2. * PI * u[2]).cos();
+        let sin_theta_i = (-cos_theta * sin_theta_op + sin_theta * cos_phi * cos_theta_op).clamp(-1., 1.);
+        let cos_theta_i = (1. - sin_theta_i * sin_theta_i).max(0.).sqrt();
+
+        let dphi = if p < P_MAX {
+            phi(p, self.gamma_o, gamma_t) + sample_trimmed_logistic(u[3], self.s, -PI, PI)
+        } else {
+            2. * PI * u[3]
+        };
+        let phi_i = phi_o + dphi;
+        let wi = Vec3::new(sin_theta_i as f64, (cos_theta_i * phi_i.cos()) as f64, (cos_theta_i * phi_i.sin()) as f64);
+
+        // pdf and value summed over all lobes
+        let mut pdf = 0.;
+        let mut f = Spectrum::zero();
+        for (i, (a, weight)) in ap.iter().zip(ap_average.iter()).enumerate() {
+            let (sin_theta_op, cos_theta_op) = self.tilt(i, sin_theta_o, cos_theta_o);
+            let mp = mp(cos_theta_i, cos_theta_op, sin_theta_i, sin_theta_op, self.v[i]);
+            let np = if i < P_MAX { np(dphi, i, self.s, self.gamma_o, gamma_t) } else { 1. / (2. * PI) };
+            pdf += mp * np * weight / total;
+            f = f + (mp * np) * *a;
+        }
+        if pdf <= 0. || !pdf.is_finite() {
+            return None;
+        }
+        Some((wi, f / pdf))
+    }
+
+    // attenuation of each lobe and the refracted azimuth
+    fn attenuation(&self, sin_theta_o: f32, cos_theta_o: f32) -> ([Spectrum; P_MAX + 1], f32) {
+        let sin_theta_t = sin_theta_o / self.eta;
+        let cos_theta_t = (1. - sin_theta_t * sin_theta_t).max(0.).sqrt();
+        // modified index for the projection onto the normal plane
+        let etap = (self.eta * self.eta - sin_theta_o * sin_theta_o).max(0.).sqrt() / cos_theta_o.max(1e-4);
+        let sin_gamma_t = (self.h / etap).clamp(-1., 1.);
+        let cos_gamma_t = (1. - sin_gamma_t * sin_gamma_t).max(0.).sqrt();
+        let gamma_t = sin_gamma_t.asin();
+
+        let segment = 2. * cos_gamma_t / cos_theta_t.max(1e-4);
+        let t = self.sigma_a.map(|s| (-s * segment).exp());
+
+        let cos_gamma_o = (1. - self.h * self.h).max(0.).sqrt();
+        let f = fresnel_dielectric(cos_theta_o * cos_gamma_o, self.eta);
+        let r = Spectrum::constant(f);
+        let tt = t.map(|t| (1. - f) * (1. - f) * t);
+        let trt = tt * t * f;
+        let rest = Spectrum::from_fn(|i| {
+            let t = t.value(i);
+            trt.value(i) * f * t / (1. - t * f).max(1e-4)
+        });
+        ([r, tt, trt, rest], gamma_t)
+    }
+
+    // scales tilt the specular cone by alpha, -2 alpha and -4 alpha for R, TT and TRT
+    fn tilt(&self, p: usize, sin_theta_o: f32, cos_theta_o: f32) -> (f32, f32) {
+        let (sin, cos) = match p {
+            0 => (sin_theta_o * self.cos_2k_alpha[1] - cos_theta_o * self.sin_2k_alpha[1],
+                  cos_theta_o * self.cos_2k_alpha[1] + sin_theta_o * self.sin_2k_alpha[1]),
+            1 => (sin_theta_o * self.cos_2k_alpha[0] + cos_theta_o * self.sin_2k_alpha[0],
+                  cos_theta_o * self.cos_2k_alpha[0] - sin_theta_o * self.sin_2k_alpha[0]),
+            2 => (sin_theta_o * self.cos_2k_alpha[2] + cos_theta_o * self.sin_2k_alpha[2],
+                  cos_theta_o * self.cos_2k_alpha[2] - sin_theta_o * self.sin_2k_alpha[2]),
+            _ => (sin_theta_o, cos_theta_o)
+        };
+        (sin, cos.abs())
+    }
+}
+
+fn fresnel_dielectric(cos_i: f32, eta: f32) -> f32 {
+    let cos_i = cos_i.clamp(0., 1.);
+    let sin_t_sq = (1. - cos_i * cos_i) / (eta * eta);
+    if sin_t_sq >= 1. {
+        return 1.;
+    }
+    let cos_t = (1. - sin_t_sq).sqrt();
+    let rs = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    let rp = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    0.5 * (rs * rs + rp * rp)
+}
+
+// longitudinal scattering
+fn mp(cos_theta_i: f32, cos_theta_o: f32, sin_theta_i: f32, sin_theta_o: f32, v: f32) -> f32 {
+    let a = cos_theta_i * cos_theta_o / v;
+    let b = sin_theta_i * sin_theta_o / v;
+    if v <= 0.1 {
+        (log_i0(a) - b - 1. / v + std::f32::consts::LN_2 + (1. / (2. * v)).ln()).exp()
+    } else {
+        ((-b).exp() * i0(a)) / ((1. / v).sinh() * 2. * v)
+    }
+}
+
+// modified Bessel function of the first kind
+fn i0(x: f32) -> f32 {
+    let mut value = 0.;
+    let mut x_2i = 1.;
+    let mut factorial = 1.;
+    let mut four_i = 1.;
+    for i in 0..10 {
+        if i > 1 {
+            factorial *= i as f32;
+        }
+        value += x_2i / (four_i * factorial * factorial);
+        x_2i *= x * x;
+        four_i *= 4.;
+    }
+    value
+}
+
+fn log_i0(x: f32) -> f32 {
+    if x > 12. {
+        x + 0.5 * (-(2. * PI).ln() + (1. / x).ln() + 1. / (8. * x))
+    } else {
+        i0(x).ln()
+    }
+}
+
+// azimuthal exit angle of lobe p
+fn phi(p: usize, gamma_o: f32, gamma_t: f32) -> f32 {
+    2. * p as f32 * gamma_t - 2. * gamma_o + p as f32 * PI
+}
+
+// azimuthal scattering
+fn np(phi_: f32, p: usize, s: f32, gamma_o: f32, gamma_t: f32) -> f32 {
+    let mut dphi = phi_ - phi(p, gamma_o, gamma_t);
+    while dphi > PI {
+        dphi -= 2. * PI;
+    }
+    while dphi < -PI {
+        dphi += 2. * PI;
+    }
+    trimmed_logistic(dphi, s, -PI, PI)
+}
+
+fn logistic(x: f32, s: f32) -> f32 {
+    let x = x.abs();
+    let e = (-x / s).exp();
+    e / (s * (1. + e) * (1. + e))
+}
+
+fn logistic_cdf(x: f32, s: f32) -> f32 {
+    1. / (1. + (-x / s).exp())
+}
+
+fn trimmed_logistic(x: f32, s: f32, a: f32, b: f32) -> f32 {
+    logistic(x, s) / (logistic_cdf(b, s) - logistic_cdf(a, s))
+}
+
+fn sample_trimmed_logistic(u: f32, s: f32, a: f32, b: f32) -> f32 {
+    let k = logistic_cdf(b, s) - logistic_cdf(a, s);
+    let x = -s * (1. / (u * k + logistic_cdf(a, s)) - 1.).ln();
+    x.clamp(a, b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ggx_visible_normal_weight_is_at_most_one() {
+        let n = 32;
+        for &alpha in &[0.05, 0.3, 0.8] {
+            for &theta in &[0.1f64, 0.7, 1.2, 1.5] {
+                let wo = Vec3::new(theta.sin(), 0., theta.cos());
+                let mut albedo = 0.;
+                for i in 0..n {
+                    for j in 0..n {
+                        let (u1, u2) = ((i as f64 + 0.5) / n as f64, (j as f64 + 0.5) / n as f64);
+                        let m = sample_ggx_visible(wo, alpha, alpha, u1, u2);
+                        assert!((m.magn() - 1.).abs() < 1e-9 && m.z() > 0.);
+                        let wi = 2. * wo.dot(&m) * m - wo;
+                        if wi.z() <= 0. {
+                            continue;
+                        }
+                        let weight = ggx_reflection_weight(wo, wi, alpha, alpha);
+                        assert!(weight > 0. && weight <= 1., "weight {} at alpha {} theta {}", weight, alpha, theta);
+                        albedo += weight / (n * n) as f64;
+                    }
+                }
+                // a perfect conductor loses energy only to masking, little of it when smooth
+                assert!(albedo <= 1.);
+                if alpha < 0.1 && theta < 1.3 {
+                    assert!(albedo > 0.98, "albedo {} at alpha {} theta {}", albedo, alpha, theta);
+                }
+            }
+        }
+    }
+}
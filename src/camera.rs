@@ -1,4 +1,4 @@
-use crate::ray::Ray;
+use crate::ray::{Ray, RayDifferential};
 use crate::vector::Vec3;
 use rand::Rng;
 use std::f64::consts::PI;
@@ -12,9 +12,10 @@ pub struct Camera {
     pub origin: Vec3,
     u: Vec3,
     v: Vec3,
-    w: Vec3,
     t0: f64,
-    t1: f64
+    t1: f64,
+    // size of a pixel in (u, v), enables ray differentials
+    pixel_size: Option<(f64, f64)>
 }
 
 impl Camera {
@@ -24,9 +25,24 @@ impl Camera {
         let mut rng = rand::thread_rng();
         let random: f64 = rng.gen();
         let time = self.t0 + random * (self.t1 - self.t0);
-        Ray::new(self.origin + offset, self.lover_left_corner + u * self.horizontal + v * self.vertical - self.origin - offset, time)
+        let origin = self.origin + offset;
+        let target = self.lover_left_corner + u * self.horizontal + v * self.vertical;
+
+        let differential = self.pixel_size.map(|(du, dv)| RayDifferential {
+            rx_origin: origin,
+            rx_direction: target + du * self.horizontal - origin,
+            ry_origin: origin,
+            ry_direction: target + dv * self.vertical - origin
+        });
+
+        Ray::with_differential(origin, target - origin, time, differential)
+    }
+
+    pub fn set_resolution(&mut self, nx: u32, ny: u32) {
+        self.pixel_size = Some((1. / nx as f64, 1. / ny as f64));
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn new(position: Vec3, look_at: Vec3, up: Vec3, vfov: f64, aspect: f32, aperture: f64, focus_dist: f64, t0: f64, t1: f64) -> Camera {
         let theta: f64 = vfov * PI / 180.;
         let half_height = (theta / 2.).tan();
@@ -41,11 +57,13 @@ impl Camera {
             vertical: 2. * half_height * focus_dist * v,
             origin: position,
             lens_radius: aperture / 2.,
-            u, v, w, t0, t1
+            u, v, t0, t1,
+            pixel_size: None
         }
     }
 }
 
+#[allow(clippy::needless_return)]
 fn random_in_unit_disk() -> Vec3 {
     let mut rng = rand::thread_rng();
     let mut p;
@@ -7,6 +7,14 @@ fn gamma_encode(linear: f32) -> f32 {
     linear.powf(1.0 / GAMMA)
 }
 
+pub fn srgb_decode(encoded: f32) -> f32 {
+    if encoded <= 0.04045 {
+        encoded / 12.92
+    } else {
+        ((encoded + 0.055) / 1.055).powf(2.4)
+    }
+}
+
 #[derive(Copy, Clone, Debug)]
 pub struct Color {
     r: f32,
@@ -16,9 +24,9 @@ pub struct Color {
 impl Color {
     pub fn clamp(&self) -> Color {
         Color {
-            r: self.r.min(1.0).max(0.0),
-            g: self.g.min(1.0).max(0.0),
-            b: self.b.min(1.0).max(0.0),
+            r: self.r.clamp(0.0, 1.0),
+            g: self.g.clamp(0.0, 1.0),
+            b: self.b.clamp(0.0, 1.0),
         }
     }
 
@@ -58,6 +66,25 @@ impl Color {
     pub fn new(r: f32, g: f32, b: f32) -> Color {
         Color { r, g, b }
     }
+
+    // decodes 8-bit sRGB texel into linear color
+    pub fn from_srgb8(r: u8, g: u8, b: u8) -> Color {
+        Color {
+            r: srgb_decode(r as f32 / 255.),
+            g: srgb_decode(g as f32 / 255.),
+            b: srgb_decode(b as f32 / 255.)
+        }
+    }
+
+    pub fn r(&self) -> f32 { self.r }
+
+    pub fn g(&self) -> f32 { self.g }
+
+    pub fn b(&self) -> f32 { self.b }
+
+    pub fn luminance(&self) -> f32 {
+        0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+    }
 }
 
 impl Add for Color {
@@ -119,3 +146,20 @@ impl Div<f32> for Color {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn srgb_decode_endpoints_and_midpoint() {
+        assert_eq!(srgb_decode(0.), 0.);
+        assert!((srgb_decode(1.) - 1.).abs() < 1e-6);
+        // linear segment below the knee
+        assert!((srgb_decode(0.04) - 0.04 / 12.92).abs() < 1e-7);
+        // sRGB 0.5 is about 21.4% linear
+        assert!((srgb_decode(0.5) - 0.214).abs() < 1e-3);
+        let c = Color::from_srgb8(255, 0, 128);
+        assert!((c.r() - 1.).abs() < 1e-6 && c.g() == 0. && (c.b() - 0.2158).abs() < 1e-3);
+    }
+}
@@ -1,50 +1,2039 @@
-use crate::scene::{SceneItem, Sphere, MovingSphere};
-use crate::ray::Ray;
+use crate::scene::{SceneItem, Sphere, MovingSphere, ConstantMedium, HeterogeneousMedium, Plane, Disk, Rect, Cuboid, Cylinder, Cone, Torus, Csg, SdfObject, Heightfield, Curve, CurveShape, Animated, Triangle, Quadric, Implicit, BilinearPatch, BezierPatch};
+use crate::ray::{Ray, RayDifferential};
+use crate::vector::{Vec3, Frame};
+use crate::poly::{solve_quadratic, solve_quartic, bezier_eval, bezier_split, bezier_patch_eval, bezier_patch_split_u, bezier_patch_split_v};
+use crate::texture::TextureCoords;
+use crate::aabb::BoundingBox;
 
-pub struct Intersection {
-    pub intersected: SceneItem,
-    pub dist: f64
+use rand::Rng;
+use std::f64::consts::PI;
+use std::cmp::Ordering;
+
+// hits closer than this are treated as self-intersections
+pub const T_MIN: f64 = 0.001;
+
+pub struct Intersection<'a> {
+    pub intersected: &'a SceneItem,
+    pub hit: Hit<'a>
+}
+
+// where a ray meets a surface, with what finding the hit already told about it
+pub struct Hit<'a> {
+    pub dist: f64,
+    // surface coordinates, for primitives that solve for them
+    pub uv: Option<(f64, f64)>,
+    // the hit inside an animated group, in the local space of its items
+    pub inner: Option<Box<Intersection<'a>>>
+}
+
+impl<'a> Hit<'a> {
+    pub fn new(dist: f64) -> Hit<'a> {
+        Hit { dist, uv: None, inner: None }
+    }
+}
+
+// local geometry at a hit point, the normal of flat surfaces may face the ray
+pub struct SurfaceInfo {
+    pub point: Vec3,
+    pub normal: Vec3,
+    pub coords: TextureCoords,
+    pub dpdu: Vec3,
+    pub dpdv: Vec3,
+    pub dndu: Vec3,
+    pub dndv: Vec3,
+    // orthonormal frame around the normal, tangent follows dpdu
+    pub tangent: Vec3,
+    pub bitangent: Vec3,
+    // the ray arrives from outside, on the side the geometric normal points to
+    pub front_face: bool
+}
+
+// change of the hit point and its texture coords between neighbouring pixels
+pub struct SurfaceDifferentials {
+    pub dpdx: Vec3,
+    pub dpdy: Vec3,
+    pub dudx: f64,
+    pub dvdx: f64,
+    pub dudy: f64,
+    pub dvdy: f64
+}
+
+impl SurfaceInfo {
+    pub fn differentials(&self, ray: &Ray) -> Option<SurfaceDifferentials> {
+        let rd = ray.differential()?;
+
+        // intersect offset rays with the tangent plane
+        let d = self.normal.dot(&self.point);
+        let tx = (d - self.normal.dot(&rd.rx_origin)) / self.normal.dot(&rd.rx_direction);
+        let ty = (d - self.normal.dot(&rd.ry_origin)) / self.normal.dot(&rd.ry_direction);
+        if !tx.is_finite() || !ty.is_finite() {
+            return None;
+        }
+
+        let dpdx = rd.rx_origin + tx * rd.rx_direction - self.point;
+        let dpdy = rd.ry_origin + ty * rd.ry_direction - self.point;
+        let (dudx, dvdx) = self.uv_offset(&dpdx);
+        let (dudy, dvdy) = self.uv_offset(&dpdy);
+
+        Some(SurfaceDifferentials { dpdx, dpdy, dudx, dvdx, dudy, dvdy })
+    }
+
+    pub fn texture_coords(&self, differentials: Option<&SurfaceDifferentials>) -> TextureCoords {
+        match differentials {
+            Some(diff) => TextureCoords {
+                dudx: diff.dudx,
+                dvdx: diff.dvdx,
+                dudy: diff.dudy,
+                dvdy: diff.dvdy,
+                dpdx: diff.dpdx,
+                dpdy: diff.dpdy,
+                ..self.coords
+            },
+            None => self.coords
+        }
+    }
+
+    // least squares solution of dp = du * dpdu + dv * dpdv
+    fn uv_offset(&self, dp: &Vec3) -> (f64, f64) {
+        let a = self.dpdu.dot(&self.dpdu);
+        let b = self.dpdu.dot(&self.dpdv);
+        let c = self.dpdv.dot(&self.dpdv);
+        let r1 = self.dpdu.dot(dp);
+        let r2 = self.dpdv.dot(dp);
+        let det = a * c - b * b;
+        if det.abs() < 1e-12 {
+            return (0., 0.);
+        }
+        ((c * r1 - b * r2) / det, (a * r2 - b * r1) / det)
+    }
+}
+
+pub fn tangent_frame(normal: &Vec3, dpdu: &Vec3) -> (Vec3, Vec3) {
+    let mut tangent = *dpdu - normal.dot(dpdu) * *normal;
+    if tangent.magn() < 1e-9 {
+        // degenerate parametrization (e.g. at a pole), pick any perpendicular
+        let axis = if normal.x().abs() > 0.9 { Vec3::new(0., 1., 0.) } else { Vec3::new(1., 0., 0.) };
+        tangent = axis.cross(normal);
+    }
+    let tangent = tangent.normalize();
+    (tangent, normal.cross(&tangent))
 }
 
 pub trait Intersectable {
-    fn intersect(&self, ray: &Ray) -> f64;
+    // distance to the closest hit further than `t_min`, negative on miss
+    fn intersect(&self, ray: &Ray, t_min: f64) -> f64;
+
+    // the same hit, primitives that solve for their surface coordinates keep them
+    fn hit(&self, ray: &Ray, t_min: f64) -> Option<Hit<'_>> {
+        let dist = self.intersect(ray, t_min);
+        if dist > 0. { Some(Hit::new(dist)) } else { None }
+    }
+
+    fn surface(&self, ray: &Ray, hit: &Hit) -> SurfaceInfo;
+}
+
+// ray parameters where the ray enters and leaves a solid
+pub type Interval = (f64, f64);
+
+// closed items that know every part of the ray inside them, needed for CSG
+pub trait Solid {
+    // sorted and disjoint, over the whole line of the ray including negative distances
+    fn intervals(&self, ray: &Ray) -> Vec<Interval>;
+}
+
+// pairs sorted crossings of a closed surface into inside intervals
+fn pair_crossings(mut crossings: Vec<f64>) -> Vec<Interval> {
+    crossings.retain(|t| t.is_finite());
+    crossings.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    crossings.chunks_exact(2).map(|pair| (pair[0], pair[1])).collect()
+}
+
+// first hit on `item` that is not cut out by its opacity
+pub fn closest_hit<'a>(item: &'a SceneItem, ray: &Ray, t_min: f64) -> Option<Intersection<'a>> {
+    let mut t_min = t_min;
+    loop {
+        let hit = item.hit(ray, t_min)?;
+        // cut out texels let the ray through to the next hit
+        if item.is_cut_out(ray, &hit) {
+            t_min = hit.dist;
+            continue;
+        }
+        return Some(Intersection {
+            intersected: item,
+            hit
+        })
+    }
+}
+
+fn sphere_intersect(center: Vec3, radius: f64, ray: &Ray, t_min: f64) -> f64 {
+    let oc = ray.origin() - center;
+    let a = ray.direction().dot(&ray.direction());
+    let b = 2.0 * ray.direction().dot(&oc);
+    let c = oc.dot(&oc) - radius * radius;
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0. {
+        return -1.;
+    }
+
+    let near = (-b - discriminant.sqrt()) / (2.0 * a);
+    if near > t_min {
+        return near;
+    }
+    let far = (-b + discriminant.sqrt()) / (2.0 * a);
+    if far > t_min {
+        return far;
+    }
+    -1.
+}
+
+fn sphere_crossings(center: Vec3, radius: f64, ray: &Ray) -> Vec<f64> {
+    let oc = ray.origin() - center;
+    solve_quadratic(ray.direction().dot(&ray.direction()), 2. * ray.direction().dot(&oc), oc.dot(&oc) - radius * radius)
+}
+
+fn sphere_surface(center: Vec3, radius: f64, ray: &Ray, dist: f64) -> SurfaceInfo {
+    let point = ray.point_at(dist);
+    let n = (point - center).normalize();
+    let sin_theta = (n.x() * n.x() + n.z() * n.z()).sqrt().max(1e-9);
+
+    // derivatives of the latitude-longitude parametrization
+    let dndu = 2. * PI * Vec3::new(n.z(), 0., -n.x());
+    let dndv = PI * Vec3::new(-n.y() * n.x() / sin_theta, sin_theta, -n.y() * n.z() / sin_theta);
+    let (tangent, bitangent) = tangent_frame(&n, &dndu);
+
+    SurfaceInfo {
+        point,
+        normal: n,
+        coords: TextureCoords::spherical(&n),
+        dpdu: radius * dndu,
+        dpdv: radius * dndv,
+        dndu,
+        dndv,
+        tangent,
+        bitangent,
+        front_face: ray.direction().dot(&n) < 0.
+    }
 }
 
 impl Intersectable for Sphere {
-    fn intersect(&self, ray: &Ray) -> f64 {
-        let oc = ray.origin() - self.center();
-        let a = ray.direction().dot(&ray.direction());
-        let b = 2.0 * ray.direction().dot(&oc);
-        let c = oc.dot(&oc) - self.radius() * self.radius();
-        let discriminant = b * b - 4.0 * a * c;
-        if discriminant < 0. {
+    fn intersect(&self, ray: &Ray, t_min: f64) -> f64 {
+        sphere_intersect(self.center(), self.radius(), ray, t_min)
+    }
+
+    fn surface(&self, ray: &Ray, hit: &Hit) -> SurfaceInfo {
+        sphere_surface(self.center(), self.radius(), ray, hit.dist)
+    }
+}
+
+impl Intersectable for MovingSphere {
+    fn intersect(&self, ray: &Ray, t_min: f64) -> f64 {
+        sphere_intersect(self.center(ray.time()), self.radius(), ray, t_min)
+    }
+
+    fn surface(&self, ray: &Ray, hit: &Hit) -> SurfaceInfo {
+        sphere_surface(self.center(ray.time()), self.radius(), ray, hit.dist)
+    }
+}
+
+fn plane_intersect(point: Vec3, normal: Vec3, ray: &Ray, t_min: f64) -> f64 {
+    let denom = normal.dot(&ray.direction());
+    if denom.abs() < 1e-12 {
+        return -1.;
+    }
+    let t = (point - ray.origin()).dot(&normal) / denom;
+    if t > t_min { t } else { -1. }
+}
+
+// flat surface with constant normal, turned towards the ray
+fn flat_surface(ray: &Ray, point: Vec3, normal: Vec3, coords: TextureCoords, dpdu: Vec3, dpdv: Vec3) -> SurfaceInfo {
+    let front_face = normal.dot(&ray.direction()) < 0.;
+    let normal = if front_face { normal } else { -normal };
+    let (tangent, bitangent) = tangent_frame(&normal, &dpdu);
+    SurfaceInfo {
+        point,
+        normal,
+        coords,
+        dpdu,
+        dpdv,
+        dndu: Vec3::zero(),
+        dndv: Vec3::zero(),
+        tangent,
+        bitangent,
+        front_face
+    }
+}
+
+impl Intersectable for Plane {
+    fn intersect(&self, ray: &Ray, t_min: f64) -> f64 {
+        plane_intersect(self.point(), self.normal(), ray, t_min)
+    }
+
+    fn surface(&self, ray: &Ray, hit: &Hit) -> SurfaceInfo {
+        let point = ray.point_at(hit.dist);
+        let (t, b) = tangent_frame(&self.normal(), &Vec3::zero());
+        let d = point - self.point();
+        flat_surface(ray, point, self.normal(), TextureCoords::new(d.dot(&t), d.dot(&b)), t, b)
+    }
+}
+
+impl Intersectable for Disk {
+    fn intersect(&self, ray: &Ray, t_min: f64) -> f64 {
+        let t = plane_intersect(self.center(), self.normal(), ray, t_min);
+        if t <= 0. || (ray.point_at(t) - self.center()).magn() > self.radius() {
+            return -1.;
+        }
+        t
+    }
+
+    fn surface(&self, ray: &Ray, hit: &Hit) -> SurfaceInfo {
+        let point = ray.point_at(hit.dist);
+        let (t, b) = tangent_frame(&self.normal(), &Vec3::zero());
+        let d = point - self.center();
+        let (x, y) = (d.dot(&t), d.dot(&b));
+        let r = (x * x + y * y).sqrt();
+        let mut phi = y.atan2(x);
+        if phi < 0. {
+            phi += 2. * PI;
+        }
+
+        let dpdu = 2. * PI * (-y * t + x * b);
+        let dpdv = if r > 1e-9 { self.radius() / r * d } else { self.radius() * t };
+        let coords = TextureCoords::new(phi / (2. * PI), r / self.radius());
+        flat_surface(ray, point, self.normal(), coords, dpdu, dpdv)
+    }
+}
+
+impl Intersectable for Rect {
+    fn intersect(&self, ray: &Ray, t_min: f64) -> f64 {
+        let (ia, ib, ik) = self.plane().axes();
+        let dk = ray.direction().get_ind(ik);
+        if dk.abs() < 1e-12 {
+            return -1.;
+        }
+        let t = (self.k() - ray.origin().get_ind(ik)) / dk;
+        if t <= t_min {
             return -1.;
+        }
+        let p = ray.point_at(t);
+        let (a0, a1, b0, b1) = self.bounds();
+        let (a, b) = (p.get_ind(ia), p.get_ind(ib));
+        if a < a0 || a > a1 || b < b0 || b > b1 {
+            return -1.;
+        }
+        t
+    }
+
+    fn surface(&self, ray: &Ray, hit: &Hit) -> SurfaceInfo {
+        let point = ray.point_at(hit.dist);
+        let (ia, ib, _) = self.plane().axes();
+        let (a0, a1, b0, b1) = self.bounds();
+        let coords = TextureCoords::new((point.get_ind(ia) - a0) / (a1 - a0), (point.get_ind(ib) - b0) / (b1 - b0));
+        let dpdu = self.point(a1, b0) - self.point(a0, b0);
+        let dpdv = self.point(a0, b1) - self.point(a0, b0);
+        flat_surface(ray, point, dpdu.cross(&dpdv).normalize(), coords, dpdu, dpdv)
+    }
+}
+
+// nearest of all crossings with a surface beyond `t_min`, negative if there is none
+fn nearest_after(crossings: &[f64], t_min: f64) -> f64 {
+    crossings.iter().fold(-1., |best, &t| if t > t_min && (best < 0. || t < best) { t } else { best })
+}
+
+fn azimuth(local: &Vec3) -> f64 {
+    let phi = local.z().atan2(local.x());
+    if phi < 0. { phi + 2. * PI } else { phi }
+}
+
+// surface info from vectors in the local space of `frame`, open surfaces turn the normal towards the ray
+fn local_surface(frame: &Frame, ray: &Ray, point: Vec3, normal: Vec3, coords: TextureCoords, derivatives: [Vec3; 4], two_sided: bool) -> SurfaceInfo {
+    let [dpdu, dpdv, dndu, dndv] = derivatives;
+    let mut normal = frame.vector_to_world(normal);
+    let (mut dndu, mut dndv) = (frame.vector_to_world(dndu), frame.vector_to_world(dndv));
+    let front_face = normal.dot(&ray.direction()) < 0.;
+    if two_sided && !front_face {
+        normal = -normal;
+        dndu = -dndu;
+        dndv = -dndv;
+    }
+    let dpdu = frame.vector_to_world(dpdu);
+    let (tangent, bitangent) = tangent_frame(&normal, &dpdu);
+    SurfaceInfo {
+        point,
+        normal,
+        coords,
+        dpdu,
+        dpdv: frame.vector_to_world(dpdv),
+        dndu,
+        dndv,
+        tangent,
+        bitangent,
+        front_face
+    }
+}
+
+impl Cuboid {
+    fn crossings(&self, ray: &Ray) -> Vec<f64> {
+        match self.bounding_box().clip(ray) {
+            Some((t_near, t_far)) => vec![t_near, t_far],
+            None => vec![]
+        }
+    }
+}
+
+impl Intersectable for Cuboid {
+    fn intersect(&self, ray: &Ray, t_min: f64) -> f64 {
+        nearest_after(&self.crossings(ray), t_min)
+    }
+
+    fn surface(&self, ray: &Ray, hit: &Hit) -> SurfaceInfo {
+        let point = ray.point_at(hit.dist);
+        let size = self.max() - self.min();
+
+        // the face is the axis where the point is relatively furthest from the center
+        let local = point - self.center();
+        let mut axis = 0;
+        let mut furthest = 0.;
+        for i in 0..3 {
+            let d = (local.get_ind(i) / size.get_ind(i)).abs();
+            if d > furthest {
+                furthest = d;
+                axis = i;
+            }
+        }
+        let (ia, ib) = ((axis + 1) % 3, (axis + 2) % 3);
+        let unit = |i: u8| {
+            let mut v = [0.; 3];
+            v[i as usize] = 1.;
+            Vec3::new(v[0], v[1], v[2])
+        };
+        let sign = local.get_ind(axis).signum();
+        let coords = TextureCoords::new(
+            (point.get_ind(ia) - self.min().get_ind(ia)) / size.get_ind(ia),
+            (point.get_ind(ib) - self.min().get_ind(ib)) / size.get_ind(ib));
+        let normal = sign * unit(axis);
+        let dpdu = size.get_ind(ia) * unit(ia);
+        let (tangent, bitangent) = tangent_frame(&normal, &dpdu);
+
+        SurfaceInfo {
+            point,
+            normal,
+            coords,
+            dpdu,
+            dpdv: size.get_ind(ib) * unit(ib),
+            dndu: Vec3::zero(),
+            dndv: Vec3::zero(),
+            tangent,
+            bitangent,
+            front_face: ray.direction().dot(&normal) < 0.
+        }
+    }
+}
+
+// distances where the ray crosses the end planes y = 0 and y = `height` inside `radius`
+fn cap_crossings(o: &Vec3, d: &Vec3, caps: &[(f64, f64)], hits: &mut Vec<f64>) {
+    if d.y().abs() < 1e-12 {
+        return;
+    }
+    for &(y, radius) in caps {
+        let t = (y - o.y()) / d.y();
+        let x = o.x() + t * d.x();
+        let z = o.z() + t * d.z();
+        if x * x + z * z <= radius * radius {
+            hits.push(t);
+        }
+    }
+}
+
+impl Cylinder {
+    fn crossings(&self, ray: &Ray) -> Vec<f64> {
+        let o = self.frame().point_to_local(ray.origin());
+        let d = self.frame().vector_to_local(ray.direction());
+        let (r, h) = (self.radius(), self.height());
+
+        let mut hits: Vec<f64> = solve_quadratic(
+            d.x() * d.x() + d.z() * d.z(),
+            2. * (o.x() * d.x() + o.z() * d.z()),
+            o.x() * o.x() + o.z() * o.z() - r * r)
+            .into_iter()
+            .filter(|t| {
+                let y = o.y() + t * d.y();
+                (0. ..=h).contains(&y)
+            })
+            .collect();
+        if self.capped() {
+            cap_crossings(&o, &d, &[(0., r), (h, r)], &mut hits);
+        }
+        hits
+    }
+}
+
+impl Intersectable for Cylinder {
+    fn intersect(&self, ray: &Ray, t_min: f64) -> f64 {
+        nearest_after(&self.crossings(ray), t_min)
+    }
+
+    fn surface(&self, ray: &Ray, hit: &Hit) -> SurfaceInfo {
+        let point = ray.point_at(hit.dist);
+        let l = self.frame().point_to_local(point);
+        let (r, h) = (self.radius(), self.height());
+        let eps = 1e-6 * (r + h);
+        let rho = (l.x() * l.x() + l.z() * l.z()).sqrt();
+        let phi = azimuth(&l);
+        let around = 2. * PI * Vec3::new(-l.z(), 0., l.x());
+
+        let on_cap = self.capped() && (l.y() < eps || l.y() > h - eps) && rho < r - eps;
+        if on_cap {
+            let normal = if l.y() < eps { Vec3::new(0., -1., 0.) } else { Vec3::new(0., 1., 0.) };
+            let radial = if rho > 1e-9 { r / rho * Vec3::new(l.x(), 0., l.z()) } else { Vec3::new(r, 0., 0.) };
+            let coords = TextureCoords::new(phi / (2. * PI), rho / r);
+            return local_surface(self.frame(), ray, point, normal, coords, [around, radial, Vec3::zero(), Vec3::zero()], false);
+        }
+
+        let normal = Vec3::new(l.x() / r, 0., l.z() / r);
+        let coords = TextureCoords::new(phi / (2. * PI), l.y() / h);
+        local_surface(self.frame(), ray, point, normal, coords, [around, Vec3::new(0., h, 0.), around / r, Vec3::zero()], !self.capped())
+    }
+}
+
+impl Cone {
+    fn crossings(&self, ray: &Ray) -> Vec<f64> {
+        let o = self.frame().point_to_local(ray.origin());
+        let d = self.frame().vector_to_local(ray.direction());
+        let (r, h) = (self.radius(), self.height());
+        let k2 = (r / h) * (r / h);
+        let hy = h - o.y();
+
+        let mut hits: Vec<f64> = solve_quadratic(
+            d.x() * d.x() + d.z() * d.z() - k2 * d.y() * d.y(),
+            2. * (o.x() * d.x() + o.z() * d.z() + k2 * hy * d.y()),
+            o.x() * o.x() + o.z() * o.z() - k2 * hy * hy)
+            .into_iter()
+            .filter(|t| {
+                let y = o.y() + t * d.y();
+                (0. ..=h).contains(&y)
+            })
+            .collect();
+        if self.capped() {
+            cap_crossings(&o, &d, &[(0., r)], &mut hits);
+        }
+        hits
+    }
+}
+
+impl Intersectable for Cone {
+    fn intersect(&self, ray: &Ray, t_min: f64) -> f64 {
+        nearest_after(&self.crossings(ray), t_min)
+    }
+
+    fn surface(&self, ray: &Ray, hit: &Hit) -> SurfaceInfo {
+        let point = ray.point_at(hit.dist);
+        let l = self.frame().point_to_local(point);
+        let (r, h) = (self.radius(), self.height());
+        let eps = 1e-6 * (r + h);
+        let rho = (l.x() * l.x() + l.z() * l.z()).sqrt();
+        let phi = azimuth(&l);
+        let around = 2. * PI * Vec3::new(-l.z(), 0., l.x());
+
+        if self.capped() && l.y() < eps && rho < r - eps {
+            let radial = if rho > 1e-9 { r / rho * Vec3::new(l.x(), 0., l.z()) } else { Vec3::new(r, 0., 0.) };
+            let coords = TextureCoords::new(phi / (2. * PI), rho / r);
+            return local_surface(self.frame(), ray, point, Vec3::new(0., -1., 0.), coords, [around, radial, Vec3::zero(), Vec3::zero()], false);
+        }
+
+        // gradient of x^2 + z^2 - (r / h)^2 (h - y)^2
+        let gradient = Vec3::new(l.x(), (r / h) * (r / h) * (h - l.y()), l.z());
+        let normal = if gradient.magn() > 1e-12 { gradient.normalize() } else { Vec3::new(0., 1., 0.) };
+        let coords = TextureCoords::new(phi / (2. * PI), l.y() / h);
+        let dpdv = Vec3::new(-r * phi.cos(), h, -r * phi.sin());
+        let dndu = 2. * PI * Vec3::new(-normal.z(), 0., normal.x());
+        local_surface(self.frame(), ray, point, normal, coords, [around, dpdv, dndu, Vec3::zero()], !self.capped())
+    }
+}
+
+impl Torus {
+    fn crossings(&self, ray: &Ray) -> Vec<f64> {
+        let o = self.frame().point_to_local(ray.origin());
+        let d = self.frame().vector_to_local(ray.direction());
+        let (big_r, r) = (self.major_radius(), self.minor_radius());
+
+        // (|p|^2 - R^2 - r^2)^2 = 4 R^2 (r^2 - y^2) along the ray
+        let dd = d.dot(&d);
+        let e = o.dot(&o) - big_r * big_r - r * r;
+        let f = o.dot(&d);
+        let four_r2 = 4. * big_r * big_r;
+        solve_quartic(
+            dd * dd,
+            4. * dd * f,
+            2. * dd * e + 4. * f * f + four_r2 * d.y() * d.y(),
+            4. * f * e + 2. * four_r2 * o.y() * d.y(),
+            e * e - four_r2 * (r * r - o.y() * o.y()))
+            .into_iter()
+            .filter(|t| t.is_finite())
+            .collect()
+    }
+}
+
+impl Intersectable for Torus {
+    fn intersect(&self, ray: &Ray, t_min: f64) -> f64 {
+        nearest_after(&self.crossings(ray), t_min)
+    }
+
+    fn surface(&self, ray: &Ray, hit: &Hit) -> SurfaceInfo {
+        let point = ray.point_at(hit.dist);
+        let l = self.frame().point_to_local(point);
+        let (big_r, r) = (self.major_radius(), self.minor_radius());
+        let phi = azimuth(&l);
+        let rho = (l.x() * l.x() + l.z() * l.z()).sqrt();
+        let mut theta = l.y().atan2(rho - big_r);
+        if theta < 0. {
+            theta += 2. * PI;
+        }
+
+        let (sin_phi, cos_phi) = phi.sin_cos();
+        let (sin_theta, cos_theta) = theta.sin_cos();
+        let normal = Vec3::new(cos_theta * cos_phi, sin_theta, cos_theta * sin_phi);
+        let ring = big_r + r * cos_theta;
+        let derivatives = [
+            2. * PI * Vec3::new(-ring * sin_phi, 0., ring * cos_phi),
+            2. * PI * r * Vec3::new(-sin_theta * cos_phi, cos_theta, -sin_theta * sin_phi),
+            2. * PI * Vec3::new(-cos_theta * sin_phi, 0., cos_theta * cos_phi),
+            2. * PI * Vec3::new(-sin_theta * cos_phi, cos_theta, -sin_theta * sin_phi)
+        ];
+        let coords = TextureCoords::new(phi / (2. * PI), theta / (2. * PI));
+        local_surface(self.frame(), ray, point, normal, coords, derivatives, false)
+    }
+}
+
+impl Solid for Sphere {
+    fn intervals(&self, ray: &Ray) -> Vec<Interval> {
+        pair_crossings(sphere_crossings(self.center(), self.radius(), ray))
+    }
+}
+
+impl Solid for MovingSphere {
+    fn intervals(&self, ray: &Ray) -> Vec<Interval> {
+        pair_crossings(sphere_crossings(self.center(ray.time()), self.radius(), ray))
+    }
+}
+
+impl Solid for Cuboid {
+    fn intervals(&self, ray: &Ray) -> Vec<Interval> {
+        pair_crossings(self.crossings(ray))
+    }
+}
+
+impl Solid for Cylinder {
+    fn intervals(&self, ray: &Ray) -> Vec<Interval> {
+        if !self.capped() {
+            return vec![];
+        }
+        pair_crossings(self.crossings(ray))
+    }
+}
+
+impl Solid for Cone {
+    fn intervals(&self, ray: &Ray) -> Vec<Interval> {
+        if !self.capped() {
+            return vec![];
+        }
+        pair_crossings(self.crossings(ray))
+    }
+}
+
+impl Solid for Torus {
+    fn intervals(&self, ray: &Ray) -> Vec<Interval> {
+        pair_crossings(self.crossings(ray))
+    }
+}
+
+// boolean combination of two sorted interval lists
+fn combine_intervals(left: &[Interval], right: &[Interval], inside: impl Fn(bool, bool) -> bool) -> Vec<Interval> {
+    // (distance, from left, entering)
+    let mut events = Vec::with_capacity(2 * (left.len() + right.len()));
+    for &(t0, t1) in left {
+        events.push((t0, true, true));
+        events.push((t1, true, false));
+    }
+    for &(t0, t1) in right {
+        events.push((t0, false, true));
+        events.push((t1, false, false));
+    }
+    events.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+
+    let mut result = Vec::new();
+    let (mut in_left, mut in_right, mut was_inside) = (false, false, false);
+    let mut start = 0.;
+    for (t, from_left, entering) in events {
+        if from_left {
+            in_left = entering;
         } else {
-            return (-b - discriminant.sqrt()) / (2.0 * a)
+            in_right = entering;
         }
+        let now_inside = inside(in_left, in_right);
+        if now_inside && !was_inside {
+            start = t;
+        } else if !now_inside && was_inside && t > start {
+            result.push((start, t));
+        }
+        was_inside = now_inside;
     }
+    result
 }
 
-impl Intersectable for MovingSphere {
-    fn intersect(&self, ray: &Ray) -> f64 {
-        let oc = ray.origin() - self.center(ray.time());
-        let a = ray.direction().dot(&ray.direction());
-        let b = 2.0 * ray.direction().dot(&oc);
-        let c = oc.dot(&oc) - self.radius() * self.radius();
-        let discriminant = b * b - 4.0 * a * c;
-        if discriminant <= 0. {
+impl Solid for Csg {
+    fn intervals(&self, ray: &Ray) -> Vec<Interval> {
+        let op = self.op();
+        combine_intervals(&self.left().intervals(ray), &self.right().intervals(ray), |l, r| op.inside(l, r))
+    }
+}
+
+// distance from `t` to the closest interval boundary
+fn boundary_distance(intervals: &[Interval], t: f64) -> f64 {
+    intervals.iter().fold(f64::MAX, |best, &(t0, t1)| best.min((t0 - t).abs()).min((t1 - t).abs()))
+}
+
+impl Intersectable for Csg {
+    fn intersect(&self, ray: &Ray, t_min: f64) -> f64 {
+        for (t0, t1) in self.intervals(ray) {
+            if t0 > t_min {
+                return t0;
+            }
+            if t1 > t_min {
+                return t1;
+            }
+        }
+        -1.
+    }
+
+    fn surface(&self, ray: &Ray, hit: &Hit) -> SurfaceInfo {
+        // the surface belongs to the operand with a boundary at the hit
+        let left = boundary_distance(&self.left().intervals(ray), hit.dist);
+        let right = boundary_distance(&self.right().intervals(ray), hit.dist);
+        let operand = if left <= right { self.left() } else { self.right() };
+        let mut surface = operand.surface(ray, hit);
+
+        // turn the normal outwards of the result, against the ray where it enters
+        let entering = self.intervals(ray).iter().any(|&(t0, _)| (t0 - hit.dist).abs() <= (hit.dist.abs() + 1.) * 1e-9);
+        let facing = surface.normal.dot(&ray.direction()) < 0.;
+        if entering != facing {
+            surface.normal = -surface.normal;
+            surface.dndu = -surface.dndu;
+            surface.dndv = -surface.dndv;
+            let (tangent, bitangent) = tangent_frame(&surface.normal, &surface.dpdu);
+            surface.tangent = tangent;
+            surface.bitangent = bitangent;
+        }
+        surface.front_face = entering;
+        surface
+    }
+}
+
+impl Intersectable for SdfObject {
+    fn intersect(&self, ray: &Ray, t_min: f64) -> f64 {
+        let (entry, exit) = match self.bounds().clip(ray) {
+            Some(span) => span,
+            None => return -1.
+        };
+        let ray_length = ray.direction().magn();
+        // step off `t_min` first, a previous hit there would be found again
+        let mut t = entry.max(t_min + self.epsilon() / ray_length);
+        // rays starting inside march on the negated distance
+        let sign = if self.distance(&ray.point_at(t)) < 0. { -1. } else { 1. };
+        for _ in 0..self.max_steps() {
+            if t > exit {
+                return -1.;
+            }
+            let d = sign * self.distance(&ray.point_at(t));
+            if d < self.epsilon() {
+                return t;
+            }
+            t += d / ray_length;
+        }
+        -1.
+    }
+
+    fn surface(&self, ray: &Ray, hit: &Hit) -> SurfaceInfo {
+        let point = ray.point_at(hit.dist);
+        // central differences of the distance
+        let h = self.epsilon().max(1e-6);
+        let gradient = |axis: Vec3| self.distance(&(point + h * axis)) - self.distance(&(point - h * axis));
+        let normal = Vec3::new(gradient(Vec3::new(1., 0., 0.)), gradient(Vec3::new(0., 1., 0.)), gradient(Vec3::new(0., 0., 1.)));
+        let normal = if normal.magn() > 0. { normal.normalize() } else { -ray.direction().normalize() };
+        let (tangent, bitangent) = tangent_frame(&normal, &Vec3::zero());
+
+        SurfaceInfo {
+            point,
+            normal,
+            coords: TextureCoords::spherical(&normal),
+            dpdu: tangent,
+            dpdv: bitangent,
+            dndu: Vec3::zero(),
+            dndv: Vec3::zero(),
+            tangent,
+            bitangent,
+            front_face: ray.direction().dot(&normal) < 0.
+        }
+    }
+}
+
+impl Intersectable for Quadric {
+    fn intersect(&self, ray: &Ray, t_min: f64) -> f64 {
+        let q = self.coefficients();
+        let (o, d) = (ray.origin(), ray.direction());
+        let (ox, oy, oz, dx, dy, dz) = (o.x(), o.y(), o.z(), d.x(), d.y(), d.z());
+        let a = q[0] * dx * dx + q[1] * dy * dy + q[2] * dz * dz + q[3] * dx * dy + q[4] * dx * dz + q[5] * dy * dz;
+        let b = 2. * (q[0] * ox * dx + q[1] * oy * dy + q[2] * oz * dz)
+            + q[3] * (ox * dy + oy * dx) + q[4] * (ox * dz + oz * dx) + q[5] * (oy * dz + oz * dy)
+            + q[6] * dx + q[7] * dy + q[8] * dz;
+        let c = self.value(&o);
+        let mut roots = solve_quadratic(a, b, c);
+        roots.sort_by(|x, y| x.partial_cmp(y).unwrap_or(Ordering::Equal));
+        let tolerance = 1e-9;
+        for t in roots {
+            if t <= t_min {
+                continue;
+            }
+            match self.clip() {
+                Some(clip) if !clip.contains(&ray.point_at(t), tolerance) => continue,
+                _ => return t
+            }
+        }
+        -1.
+    }
+
+    fn surface(&self, ray: &Ray, hit: &Hit) -> SurfaceInfo {
+        let point = ray.point_at(hit.dist);
+        let gradient = self.gradient(&point);
+        let mut normal = if gradient.magn() > 0. { gradient.normalize() } else { -ray.direction().normalize() };
+        let front_face = normal.dot(&ray.direction()) < 0.;
+        if !self.is_closed() && !front_face {
+            normal = -normal;
+        }
+        let (tangent, bitangent) = tangent_frame(&normal, &Vec3::zero());
+
+        SurfaceInfo {
+            point,
+            normal,
+            front_face,
+            coords: TextureCoords::spherical(&normal),
+            dpdu: tangent,
+            dpdv: bitangent,
+            dndu: Vec3::zero(),
+            dndv: Vec3::zero(),
+            tangent,
+            bitangent
+        }
+    }
+}
+
+impl Implicit {
+    // narrows a sign change in [a, b] with Newton steps, bisecting whenever a step leaves the bracket
+    fn refine(&self, ray: &Ray, mut a: f64, mut b: f64) -> f64 {
+        let f = |t: f64| self.value(&ray.point_at(t));
+        let fa = f(a);
+        let mut t = 0.5 * (a + b);
+        let tolerance = self.epsilon() / ray.direction().magn();
+        for _ in 0..64 {
+            let ft = f(t);
+            if (ft < 0.) == (fa < 0.) { a = t } else { b = t }
+            if b - a < tolerance {
+                break;
+            }
+            let h = 0.5 * tolerance.max(1e-9 * t.abs());
+            let slope = (f(t + h) - f(t - h)) / (2. * h);
+            let next = t - ft / slope;
+            t = if slope != 0. && next > a && next < b { next } else { 0.5 * (a + b) };
+        }
+        t
+    }
+}
+
+impl Intersectable for Implicit {
+    fn intersect(&self, ray: &Ray, t_min: f64) -> f64 {
+        let (entry, exit) = match self.bounds().clip(ray) {
+            Some(span) => span,
+            None => return -1.
+        };
+        let start = entry.max(t_min);
+        if start >= exit {
+            return -1.;
+        }
+        // samples sit on a fixed grid across the whole box, so hits do not depend on where the
+        // ray starts, the first step only runs up to the next grid point
+        let step = (exit - entry) / self.steps() as f64;
+        let mut k = ((start - entry) / step).floor() as u32;
+        let mut t = start;
+        let mut value = self.value(&ray.point_at(t));
+        while t < exit {
+            k += 1;
+            let next = (entry + k as f64 * step).min(exit);
+            let next_value = self.value(&ray.point_at(next));
+            if value == 0. && t > t_min {
+                return t;
+            }
+            if (value < 0.) != (next_value < 0.) {
+                let hit = self.refine(ray, t, next);
+                if hit > t_min {
+                    return hit;
+                }
+            }
+            t = next;
+            value = next_value;
+        }
+        -1.
+    }
+
+    fn surface(&self, ray: &Ray, hit: &Hit) -> SurfaceInfo {
+        let point = ray.point_at(hit.dist);
+        // central differences of the function
+        let h = (self.epsilon() * 10.).max(1e-6);
+        let gradient = |axis: Vec3| self.value(&(point + h * axis)) - self.value(&(point - h * axis));
+        let normal = Vec3::new(gradient(Vec3::new(1., 0., 0.)), gradient(Vec3::new(0., 1., 0.)), gradient(Vec3::new(0., 0., 1.)));
+        let normal = if normal.magn() > 0. { normal.normalize() } else { -ray.direction().normalize() };
+        let (tangent, bitangent) = tangent_frame(&normal, &Vec3::zero());
+
+        SurfaceInfo {
+            point,
+            normal,
+            coords: TextureCoords::spherical(&normal),
+            dpdu: tangent,
+            dpdv: bitangent,
+            dndu: Vec3::zero(),
+            dndv: Vec3::zero(),
+            tangent,
+            bitangent,
+            front_face: ray.direction().dot(&normal) < 0.
+        }
+    }
+}
+
+// Moller-Trumbore, returns the distance and the barycentric weights of p1 and p2
+pub fn triangle_intersect(p0: Vec3, p1: Vec3, p2: Vec3, ray: &Ray, t_min: f64) -> Option<(f64, f64, f64)> {
+    let e1 = p1 - p0;
+    let e2 = p2 - p0;
+    let pv = ray.direction().cross(&e2);
+    let det = e1.dot(&pv);
+    if det.abs() < 1e-14 {
+        return None;
+    }
+    let inv = 1. / det;
+    let tv = ray.origin() - p0;
+    let b1 = tv.dot(&pv) * inv;
+    if !(0. ..=1.).contains(&b1) {
+        return None;
+    }
+    let qv = tv.cross(&e1);
+    let b2 = ray.direction().dot(&qv) * inv;
+    if b2 < 0. || b1 + b2 > 1. {
+        return None;
+    }
+    let t = e2.dot(&qv) * inv;
+    if t > t_min { Some((t, b1, b2)) } else { None }
+}
+
+// nearest hit on a bilinear patch with its (u, v), for each u the ray has to be coplanar with the
+// line from lerp(u, p00, p10) to lerp(u, p01, p11), which is quadratic in u
+pub fn bilinear_intersect(p00: Vec3, p10: Vec3, p01: Vec3, p11: Vec3, ray: &Ray, t_min: f64) -> Option<(f64, f64, f64)> {
+    let coplanar = |u: f64| {
+        let start = p00 + u * (p10 - p00);
+        let along = p01 + u * (p11 - p01) - start;
+        (start - ray.origin()).dot(&along.cross(&ray.direction()))
+    };
+    // the quadratic through its values at 0, 1/2 and 1
+    let (f0, f1, f2) = (coplanar(0.), coplanar(0.5), coplanar(1.));
+    let a = 2. * f0 - 4. * f1 + 2. * f2;
+    let b = 4. * f1 - 3. * f0 - f2;
+
+    let mut best: Option<(f64, f64, f64)> = None;
+    for u in solve_quadratic(a, b, f0) {
+        if !(0. ..=1.).contains(&u) {
+            continue;
+        }
+        let start = p00 + u * (p10 - p00);
+        let along = p01 + u * (p11 - p01) - start;
+        let perp = ray.direction().cross(&along);
+        let length = perp.dot(&perp);
+        if length < 1e-18 {
+            continue;
+        }
+        let offset = start - ray.origin();
+        let t = offset.dot(&along.cross(&perp)) / length;
+        let v = offset.dot(&ray.direction().cross(&perp)) / length;
+        if t > t_min && (0. ..=1.).contains(&v) {
+            match best {
+                Some((closest, _, _)) if closest <= t => {},
+                _ => best = Some((t, u, v))
+            }
+        }
+    }
+    best
+}
+
+impl Intersectable for BilinearPatch {
+    fn intersect(&self, ray: &Ray, t_min: f64) -> f64 {
+        self.hit(ray, t_min).map_or(-1., |hit| hit.dist)
+    }
+
+    fn hit(&self, ray: &Ray, t_min: f64) -> Option<Hit<'_>> {
+        let [p00, p10, p01, p11] = self.corners();
+        bilinear_intersect(p00, p10, p01, p11, ray, t_min)
+            .map(|(dist, u, v)| Hit { dist, uv: Some((u, v)), inner: None })
+    }
+
+    fn surface(&self, ray: &Ray, hit: &Hit) -> SurfaceInfo {
+        // hits from the patch search always carry their coordinates
+        let (u, v) = hit.uv.unwrap_or((0.5, 0.5));
+        let [p00, p10, p01, p11] = self.corners();
+        let dpdu = (p10 - p00) + v * (p11 - p01 - p10 + p00);
+        let dpdv = (p01 - p00) + u * (p11 - p10 - p01 + p00);
+        let cross = dpdu.cross(&dpdv);
+        let mut normal = if cross.magn() > 0. { cross.normalize() } else { -ray.direction().normalize() };
+
+        // Weingarten equations, twisted patches only curve through the mixed derivative
+        let twist = p11 - p10 - p01 + p00;
+        let (e, f, g) = (dpdu.dot(&dpdu), dpdu.dot(&dpdv), dpdv.dot(&dpdv));
+        let m = normal.dot(&twist);
+        let det = e * g - f * f;
+        let (mut dndu, mut dndv) = if det.abs() > 1e-18 {
+            ((m * f / det) * dpdu - (m * e / det) * dpdv, -(m * g / det) * dpdu + (m * f / det) * dpdv)
+        } else {
+            (Vec3::zero(), Vec3::zero())
+        };
+        let front_face = normal.dot(&ray.direction()) < 0.;
+        if !front_face {
+            normal = -normal;
+            dndu = -dndu;
+            dndv = -dndv;
+        }
+        let (tangent, bitangent) = tangent_frame(&normal, &dpdu);
+
+        SurfaceInfo {
+            point: ray.point_at(hit.dist),
+            normal,
+            coords: TextureCoords::new(u, v),
+            dpdu,
+            dpdv,
+            dndu,
+            dndv,
+            tangent,
+            bitangent,
+            front_face
+        }
+    }
+}
+
+// pieces flatter than this fraction of the patch size take the hit of their corner bilinear patch
+const PATCH_FLATNESS: f64 = 1e-2;
+const PATCH_MAX_DEPTH: u32 = 16;
+
+// a Bezier patch in a space where the ray starts at the origin and runs along z
+struct RaySpacePatch {
+    points: [[Vec3; 4]; 4],
+    flatness: f64,
+    z_min: f64
+}
+
+impl RaySpacePatch {
+    // nearest hit (z, u, v) by splitting the control net, the patch lies in the convex hull of the
+    // control points so pieces whose points all pass the ray on one side, or lie outside
+    // (z_min, best), cannot be hit
+    fn recursive_hit(&self, cp: &[[Vec3; 4]; 4], us: (f64, f64), vs: (f64, f64), depth: u32, best: &mut Option<(f64, f64, f64)>) {
+        let (mut low, mut high) = (cp[0][0], cp[0][0]);
+        for p in cp.iter().flatten() {
+            low = Vec3::new(low.x().min(p.x()), low.y().min(p.y()), low.z().min(p.z()));
+            high = Vec3::new(high.x().max(p.x()), high.y().max(p.y()), high.z().max(p.z()));
+        }
+        let beyond_best = match *best {
+            Some((closest, _, _)) => low.z() >= closest,
+            None => false
+        };
+        if low.x() > 0. || high.x() < 0. || low.y() > 0. || high.y() < 0. || high.z() <= self.z_min || beyond_best {
+            return;
+        }
+
+        // distance of the control points from the bilinear patch through the corners
+        let (p00, p10, p01, p11) = (cp[0][0], cp[0][3], cp[3][0], cp[3][3]);
+        let mut deviation: f64 = 0.;
+        for (i, row) in cp.iter().enumerate() {
+            for (j, p) in row.iter().enumerate() {
+                let (s, t) = (j as f64 / 3., i as f64 / 3.);
+                let bilinear = (1. - s) * ((1. - t) * p00 + t * p01) + s * ((1. - t) * p10 + t * p11);
+                deviation = deviation.max((*p - bilinear).magn());
+            }
+        }
+
+        if deviation <= self.flatness || depth == PATCH_MAX_DEPTH {
+            let along_z = Ray::new(Vec3::zero(), Vec3::new(0., 0., 1.), 0.);
+            if let Some((z, s, r)) = bilinear_intersect(p00, p10, p01, p11, &along_z, self.z_min) {
+                let u = us.0 + s * (us.1 - us.0);
+                let v = vs.0 + r * (vs.1 - vs.0);
+                // a little slack keeps hits on the edges shared with neighbouring pieces
+                let (du, dv) = (1e-3 * (us.1 - us.0), 1e-3 * (vs.1 - vs.0));
+                let hit = match self.polish(z, u, v) {
+                    Some((pz, pu, pv)) if pz > self.z_min
+                        && pu >= us.0 - du && pu <= us.1 + du && pv >= vs.0 - dv && pv <= vs.1 + dv => Some((pz, pu, pv)),
+                    // the estimate is all the deepest pieces have
+                    _ if depth == PATCH_MAX_DEPTH => Some((z, u, v)),
+                    _ => None
+                };
+                if let Some((z, u, v)) = hit {
+                    match *best {
+                        Some((closest, _, _)) if closest <= z => {},
+                        _ => *best = Some((z, u.clamp(0., 1.), v.clamp(0., 1.)))
+                    }
+                    return;
+                }
+            }
+            // the bilinear estimate can miss where the curved piece is still hit, only the hull
+            // test ends the search above the deepest level
+            if depth == PATCH_MAX_DEPTH {
+                return;
+            }
+        }
+
+        // split across the longer direction
+        let u_length = (p10 - p00).magn() + (p11 - p01).magn();
+        let v_length = (p01 - p00).magn() + (p11 - p10).magn();
+        if u_length >= v_length {
+            let mid = 0.5 * (us.0 + us.1);
+            let (first, second) = bezier_patch_split_u(cp);
+            self.recursive_hit(&first, (us.0, mid), vs, depth + 1, best);
+            self.recursive_hit(&second, (mid, us.1), vs, depth + 1, best);
+        } else {
+            let mid = 0.5 * (vs.0 + vs.1);
+            let (first, second) = bezier_patch_split_v(cp);
+            self.recursive_hit(&first, us, (vs.0, mid), depth + 1, best);
+            self.recursive_hit(&second, us, (mid, vs.1), depth + 1, best);
+        }
+    }
+
+    // Newton iteration on S(u, v) - (0, 0, z) = 0, solved by Cramer's rule
+    fn polish(&self, mut z: f64, mut u: f64, mut v: f64) -> Option<(f64, f64, f64)> {
+        let dz = Vec3::new(0., 0., -1.);
+        for _ in 0..8 {
+            let (p, dpdu, dpdv) = bezier_patch_eval(&self.points, u, v);
+            let residual = p - Vec3::new(0., 0., z);
+            let det = dpdu.dot(&dpdv.cross(&dz));
+            if det.abs() < 1e-18 {
+                return None;
+            }
+            let step_u = residual.dot(&dpdv.cross(&dz)) / det;
+            let step_v = dpdu.dot(&residual.cross(&dz)) / det;
+            let step_z = dpdu.dot(&dpdv.cross(&residual)) / det;
+            u -= step_u;
+            v -= step_v;
+            z -= step_z;
+            if step_u.abs() < 1e-10 && step_v.abs() < 1e-10 {
+                return Some((z, u, v));
+            }
+        }
+        None
+    }
+}
+
+impl Intersectable for BezierPatch {
+    fn intersect(&self, ray: &Ray, t_min: f64) -> f64 {
+        self.hit(ray, t_min).map_or(-1., |hit| hit.dist)
+    }
+
+    // nearest hit and its (u, v), found by subdividing the patch in ray space
+    fn hit(&self, ray: &Ray, t_min: f64) -> Option<Hit<'_>> {
+        let length = ray.direction().magn();
+        let z_axis = ray.direction() / length;
+        let (x_axis, y_axis) = tangent_frame(&z_axis, &Vec3::zero());
+        let mut points = [[Vec3::zero(); 4]; 4];
+        for (row, world) in points.iter_mut().zip(self.points()) {
+            for (p, q) in row.iter_mut().zip(world) {
+                let d = *q - ray.origin();
+                *p = Vec3::new(d.dot(&x_axis), d.dot(&y_axis), d.dot(&z_axis));
+            }
+        }
+        let size = (self.bounding_box().max() - self.bounding_box().min()).magn();
+        let patch = RaySpacePatch { points, flatness: PATCH_FLATNESS * size, z_min: t_min * length };
+
+        let mut best = None;
+        patch.recursive_hit(&patch.points, (0., 1.), (0., 1.), 0, &mut best);
+        best.map(|(z, u, v)| Hit { dist: z / length, uv: Some((u, v)), inner: None })
+    }
+
+    fn surface(&self, ray: &Ray, hit: &Hit) -> SurfaceInfo {
+        // hits from the patch search always carry their coordinates
+        let (u, v) = hit.uv.unwrap_or((0.5, 0.5));
+        let (_, dpdu, dpdv) = bezier_patch_eval(self.points(), u, v);
+        let cross = dpdu.cross(&dpdv);
+        let normal = if cross.magn() > 0. { cross.normalize() } else { -ray.direction().normalize() };
+        flat_surface(ray, ray.point_at(hit.dist), normal, TextureCoords::new(u, v), dpdu, dpdv)
+    }
+}
+
+impl Heightfield {
+    // nearest hit on the two triangles of cell (i, j)
+    fn cell_intersect(&self, i: usize, j: usize, ray: &Ray, t_min: f64) -> Option<f64> {
+        let p00 = self.vertex(i, j);
+        let p10 = self.vertex(i + 1, j);
+        let p01 = self.vertex(i, j + 1);
+        let p11 = self.vertex(i + 1, j + 1);
+        let first = triangle_intersect(p00, p10, p11, ray, t_min).map(|hit| hit.0);
+        let second = triangle_intersect(p00, p11, p01, ray, t_min).map(|hit| hit.0);
+        match (first, second) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b)
+        }
+    }
+
+    // smooth normal from central differences of the samples
+    fn vertex_normal(&self, i: usize, j: usize) -> Vec3 {
+        let (cw, ch) = self.cell_size();
+        let (nx, nz) = self.resolution();
+        let (i0, i1) = (i.saturating_sub(1), (i + 1).min(nx - 1));
+        let (j0, j1) = (j.saturating_sub(1), (j + 1).min(nz - 1));
+        let dx = (self.height(i1, j) - self.height(i0, j)) / ((i1 - i0) as f64 * cw);
+        let dz = (self.height(i, j1) - self.height(i, j0)) / ((j1 - j0) as f64 * ch);
+        Vec3::new(-dx, 1., -dz).normalize()
+    }
+
+    fn cell_of(&self, point: &Vec3) -> (usize, usize, f64, f64) {
+        let (cw, ch) = self.cell_size();
+        let (nx, nz) = self.resolution();
+        let gx = ((point.x() - self.bounds().min().x()) / cw).clamp(0., (nx - 1) as f64);
+        let gz = ((point.z() - self.bounds().min().z()) / ch).clamp(0., (nz - 1) as f64);
+        let i = (gx.floor() as usize).min(nx - 2);
+        let j = (gz.floor() as usize).min(nz - 2);
+        (i, j, gx - i as f64, gz - j as f64)
+    }
+}
+
+impl Intersectable for Heightfield {
+    // 2D DDA over the cells, skipping cells whose height range the ray misses
+    fn intersect(&self, ray: &Ray, t_min: f64) -> f64 {
+        let (entry, exit) = match self.bounds().clip(ray) {
+            Some(span) => span,
+            None => return -1.
+        };
+        let mut t = entry.max(t_min);
+        if t > exit {
             return -1.;
+        }
+        let (cw, ch) = self.cell_size();
+        let (nx, nz) = self.resolution();
+        let min = self.bounds().min();
+        let o = ray.origin();
+        let d = ray.direction();
+
+        let (mut i, mut j, _, _) = self.cell_of(&ray.point_at(t));
+        let step_i: isize = if d.x() > 0. { 1 } else { -1 };
+        let step_j: isize = if d.z() > 0. { 1 } else { -1 };
+        let boundary = |index: usize, step: isize, size: f64, start: f64, origin: f64, dir: f64| {
+            if dir == 0. {
+                return f64::MAX;
+            }
+            let edge = if step > 0 { index + 1 } else { index };
+            (start + edge as f64 * size - origin) / dir
+        };
+        let mut next_x = boundary(i, step_i, cw, min.x(), o.x(), d.x());
+        let mut next_z = boundary(j, step_j, ch, min.z(), o.z(), d.z());
+        let delta_x = if d.x() == 0. { f64::MAX } else { cw / d.x().abs() };
+        let delta_z = if d.z() == 0. { f64::MAX } else { ch / d.z().abs() };
+
+        loop {
+            let t_out = next_x.min(next_z).min(exit);
+            let (y_in, y_out) = (o.y() + t * d.y(), o.y() + t_out * d.y());
+            let (low, high) = self.cell_range(i, j);
+            if y_in.max(y_out) >= low && y_in.min(y_out) <= high {
+                if let Some(hit) = self.cell_intersect(i, j, ray, t_min) {
+                    return hit;
+                }
+            }
+            if t_out >= exit {
+                return -1.;
+            }
+
+            if next_x < next_z {
+                let next = i as isize + step_i;
+                if next < 0 || next > nx as isize - 2 {
+                    return -1.;
+                }
+                i = next as usize;
+                t = next_x;
+                next_x += delta_x;
+            } else {
+                let next = j as isize + step_j;
+                if next < 0 || next > nz as isize - 2 {
+                    return -1.;
+                }
+                j = next as usize;
+                t = next_z;
+                next_z += delta_z;
+            }
+        }
+    }
+
+    fn surface(&self, ray: &Ray, hit: &Hit) -> SurfaceInfo {
+        let point = ray.point_at(hit.dist);
+        let (i, j, fx, fz) = self.cell_of(&point);
+        let lerp = |a: Vec3, b: Vec3, t: f64| (1. - t) * a + t * b;
+        let normal = lerp(
+            lerp(self.vertex_normal(i, j), self.vertex_normal(i + 1, j), fx),
+            lerp(self.vertex_normal(i, j + 1), self.vertex_normal(i + 1, j + 1), fx),
+            fz).normalize();
+
+        let size = self.bounds().max() - self.bounds().min();
+        let coords = TextureCoords::new((point.x() - self.bounds().min().x()) / size.x(), (point.z() - self.bounds().min().z()) / size.z());
+        // slopes of the smooth surface along x and z
+        let dpdu = Vec3::new(size.x(), -normal.x() / normal.y() * size.x(), 0.);
+        let dpdv = Vec3::new(0., -normal.z() / normal.y() * size.z(), size.z());
+        flat_surface(ray, point, normal, coords, dpdu, dpdv)
+    }
+}
+
+impl Curve {
+    fn recursive_hit(&self, cp: &[Vec3; 4], u0: f64, u1: f64, depth: u32, z_min: f64) -> Option<(f64, f64, f64)> {
+        // the ray has to pass the bounds of this piece
+        let half = 0.5 * self.width(u0).max(self.width(u1));
+        let (mut low, mut high) = (cp[0], cp[0]);
+        for p in cp.iter().skip(1) {
+            low = Vec3::new(low.x().min(p.x()), low.y().min(p.y()), low.z().min(p.z()));
+            high = Vec3::new(high.x().max(p.x()), high.y().max(p.y()), high.z().max(p.z()));
+        }
+        if low.x() - half > 0. || high.x() + half < 0. || low.y() - half > 0. || high.y() + half < 0. || high.z() + half < z_min {
+            return None;
+        }
+
+        if depth > 0 {
+            let mid = 0.5 * (u0 + u1);
+            let (first, second) = bezier_split(cp);
+            let a = self.recursive_hit(&first, u0, mid, depth - 1, z_min);
+            let b = self.recursive_hit(&second, mid, u1, depth - 1, z_min);
+            return match (a, b) {
+                (Some(a), Some(b)) => Some(if a.0 <= b.0 { a } else { b }),
+                (a, b) => a.or(b)
+            };
+        }
+
+        // the origin has to lie between the lines perpendicular to the ends
+        let start = (cp[1].y() - cp[0].y()) * -cp[0].y() + cp[0].x() * (cp[0].x() - cp[1].x());
+        let end = (cp[2].y() - cp[3].y()) * -cp[3].y() + cp[3].x() * (cp[3].x() - cp[2].x());
+        if start < 0. || end < 0. {
+            return None;
+        }
+
+        // closest point of the straightened piece to the ray
+        let (sx, sy) = (cp[3].x() - cp[0].x(), cp[3].y() - cp[0].y());
+        let denom = sx * sx + sy * sy;
+        if denom == 0. {
+            return None;
+        }
+        let w = (-cp[0].x() * sx - cp[0].y() * sy) / denom;
+        let u = (u0 + w * (u1 - u0)).clamp(u0, u1);
+        let width = self.width(u);
+        let (pc, dpcdw) = bezier_eval(cp, w.clamp(0., 1.));
+        let dist_sq = pc.x() * pc.x() + pc.y() * pc.y();
+        if dist_sq > 0.25 * width * width || pc.z() <= z_min {
+            return None;
+        }
+
+        let dist = dist_sq.sqrt();
+        let side = dpcdw.x() * -pc.y() + pc.x() * dpcdw.y();
+        let v = if side > 0. { 0.5 + dist / width } else { 0.5 - dist / width };
+        Some((pc.z(), u, v))
+    }
+}
+
+impl Intersectable for Curve {
+    fn intersect(&self, ray: &Ray, t_min: f64) -> f64 {
+        self.hit(ray, t_min).map_or(-1., |hit| hit.dist)
+    }
+
+    // hit distance and (u, v) on the curve, found by recursive subdivision in a space where
+    // the ray starts at the origin and runs along z
+    fn hit(&self, ray: &Ray, t_min: f64) -> Option<Hit<'_>> {
+        let length = ray.direction().magn();
+        let z_axis = ray.direction() / length;
+        let (x_axis, y_axis) = tangent_frame(&z_axis, &Vec3::zero());
+        let to_ray = |p: &Vec3| {
+            let d = *p - ray.origin();
+            Vec3::new(d.dot(&x_axis), d.dot(&y_axis), d.dot(&z_axis))
+        };
+        let points = self.points();
+        let cp = [to_ray(&points[0]), to_ray(&points[1]), to_ray(&points[2]), to_ray(&points[3])];
+
+        // subdivide until the pieces are close to straight relative to the width
+        let mut bend: f64 = 0.;
+        for i in 0..2 {
+            let d = cp[i] - 2. * cp[i + 1] + cp[i + 2];
+            bend = bend.max(d.x().abs()).max(d.y().abs()).max(d.z().abs());
+        }
+        let (u0, u1) = self.u_range();
+        let eps = 0.05 * self.width(u0).max(self.width(u1));
+        let depth = if bend > 0. && eps > 0. {
+            ((std::f64::consts::SQRT_2 * 6. * bend / (8. * eps)).log2() / 2.).clamp(0., 10.) as u32
         } else {
-            return (-b - discriminant.sqrt()) / (2.0 * a)
+            0
+        };
+
+        self.recursive_hit(&cp, u0, u1, depth, t_min * length)
+            .map(|(z, u, v)| Hit { dist: z / length, uv: Some((u, v)), inner: None })
+    }
+
+    fn surface(&self, ray: &Ray, hit: &Hit) -> SurfaceInfo {
+        // hits from the curve search always carry their coordinates
+        let (u, v) = hit.uv.unwrap_or((self.u_range().0, 0.5));
+        let (u0, u1) = self.u_range();
+        let (_, dpdw) = bezier_eval(self.points(), ((u - u0) / (u1 - u0)).clamp(0., 1.));
+        let dpdu = dpdw / (u1 - u0);
+
+        // across the curve in the plane facing the ray
+        let dir = ray.direction().normalize();
+        let across = dpdu.cross(&dir);
+        let across = if across.magn() > 1e-12 { across.normalize() } else { tangent_frame(&dpdu.normalize(), &Vec3::zero()).0 };
+        let mut facing = across.cross(&dpdu).normalize();
+        if facing.dot(&dir) > 0. {
+            facing = -facing;
+        }
+        let normal = match self.shape() {
+            CurveShape::Flat => facing,
+            CurveShape::Round => {
+                // bend towards the silhouette like a cylinder seen from the ray
+                let theta = (v - 0.5) * PI;
+                let side = if across.dot(&facing.cross(&dpdu)) > 0. { 1. } else { -1. };
+                (theta.cos() * facing + side * theta.sin() * across).normalize()
+            }
+        };
+        let (tangent, bitangent) = tangent_frame(&normal, &dpdu);
+
+        SurfaceInfo {
+            point: ray.point_at(hit.dist),
+            normal,
+            coords: TextureCoords::new(u, v),
+            dpdu,
+            dpdv: self.width(u) * across,
+            dndu: Vec3::zero(),
+            dndv: Vec3::zero(),
+            tangent,
+            bitangent,
+            // curves have no inside, they always face the ray
+            front_face: true
         }
     }
 }
 
+impl Intersectable for Triangle {
+    fn intersect(&self, ray: &Ray, t_min: f64) -> f64 {
+        let [p0, p1, p2] = self.vertices(ray.time());
+        match triangle_intersect(p0, p1, p2, ray, t_min) {
+            Some((t, _, _)) => t,
+            None => -1.
+        }
+    }
+
+    fn surface(&self, ray: &Ray, hit: &Hit) -> SurfaceInfo {
+        let time = ray.time();
+        let [p0, p1, p2] = self.vertices(time);
+        let point = ray.point_at(hit.dist);
+
+        // barycentric coordinates of the hit from the areas of the opposite sub-triangles
+        let face = (p1 - p0).cross(&(p2 - p0));
+        let area = face.dot(&face);
+        let b1 = (p2 - point).cross(&(p0 - point)).dot(&face) / area;
+        let b2 = (p1 - p0).cross(&(point - p0)).dot(&face) / area;
+        let b0 = 1. - b1 - b2;
+
+        let mesh = self.mesh();
+        let [i0, i1, i2] = self.indices();
+        let [uv0, uv1, uv2] = match (mesh.uv(i0), mesh.uv(i1), mesh.uv(i2)) {
+            (Some(a), Some(b), Some(c)) => [(a.u, a.v), (b.u, b.v), (c.u, c.v)],
+            _ => [(0., 0.), (1., 0.), (1., 1.)]
+        };
+        let coords = TextureCoords::new(b0 * uv0.0 + b1 * uv1.0 + b2 * uv2.0, b0 * uv0.1 + b1 * uv1.1 + b2 * uv2.1);
+
+        // position derivatives from the uv deltas of the edges
+        let (du02, dv02) = (uv0.0 - uv2.0, uv0.1 - uv2.1);
+        let (du12, dv12) = (uv1.0 - uv2.0, uv1.1 - uv2.1);
+        let (dp02, dp12) = (p0 - p2, p1 - p2);
+        let det = du02 * dv12 - dv02 * du12;
+        let (dpdu, dpdv) = if det.abs() < 1e-12 {
+            tangent_frame(&face.normalize(), &Vec3::zero())
+        } else {
+            ((dv12 * dp02 - dv02 * dp12) / det, (du02 * dp12 - du12 * dp02) / det)
+        };
+
+        // smooth normal on the side of the geometric one that faces the ray
+        let geometric = face.normalize();
+        let shading = b0 * mesh.normal(i0, time) + b1 * mesh.normal(i1, time) + b2 * mesh.normal(i2, time);
+        let shading = if shading.magn() > 0. { shading.normalize() } else { geometric };
+        let shading = if shading.dot(&geometric) < 0. { -shading } else { shading };
+        let front_face = geometric.dot(&ray.direction()) < 0.;
+        let normal = if front_face { shading } else { -shading };
+        let (tangent, bitangent) = tangent_frame(&normal, &dpdu);
+        SurfaceInfo {
+            point,
+            normal,
+            coords,
+            dpdu,
+            dpdv,
+            dndu: Vec3::zero(),
+            dndv: Vec3::zero(),
+            tangent,
+            bitangent,
+            front_face
+        }
+    }
+}
+
+impl Animated {
+    // the same ray in the items' own space, distances along it are unchanged
+    fn local_ray(&self, ray: &Ray) -> Ray {
+        let transform = self.transform(ray.time());
+        let differential = ray.differential().map(|d| RayDifferential {
+            rx_origin: transform.point_to_local(d.rx_origin),
+            rx_direction: transform.vector_to_local(d.rx_direction),
+            ry_origin: transform.point_to_local(d.ry_origin),
+            ry_direction: transform.vector_to_local(d.ry_direction)
+        });
+        Ray::with_differential(transform.point_to_local(ray.origin()), transform.vector_to_local(ray.direction()), ray.time(), differential)
+    }
+}
+
+impl Intersectable for Animated {
+    fn intersect(&self, ray: &Ray, t_min: f64) -> f64 {
+        self.hit(ray, t_min).map_or(-1., |hit| hit.dist)
+    }
+
+    // keeps the hit on the item so its surface and material can be found
+    fn hit(&self, ray: &Ray, t_min: f64) -> Option<Hit<'_>> {
+        let inner = self.items().trace(&self.local_ray(ray), t_min)?;
+        Some(Hit { dist: inner.hit.dist, uv: None, inner: Some(Box::new(inner)) })
+    }
+
+    fn surface(&self, ray: &Ray, hit: &Hit) -> SurfaceInfo {
+        let inner = hit.inner.as_ref().expect("hits on animated groups come from their items");
+        let transform = self.transform(ray.time());
+        let local = inner.intersected.surface(&self.local_ray(ray), &inner.hit);
+        let scaled = transform.covector_to_world(local.normal);
+        let normal = scaled.normalize();
+        // derivatives of the renormalized inverse transpose, only the part across the normal is left
+        let normal_derivative = |dn: Vec3| {
+            let dn = transform.covector_to_world(dn);
+            (dn - normal.dot(&dn) * normal) / scaled.magn()
+        };
+        let dpdu = transform.vector_to_world(local.dpdu);
+        let (tangent, bitangent) = tangent_frame(&normal, &dpdu);
+        SurfaceInfo {
+            point: ray.point_at(hit.dist),
+            normal,
+            coords: local.coords,
+            dpdu,
+            dpdv: transform.vector_to_world(local.dpdv),
+            dndu: normal_derivative(local.dndu),
+            dndv: normal_derivative(local.dndv),
+            tangent,
+            bitangent,
+            front_face: local.front_face
+        }
+    }
+}
+
+impl Solid for SceneItem {
+    fn intervals(&self, ray: &Ray) -> Vec<Interval> {
+        match self {
+            SceneItem::Sphere(ref s) => s.intervals(ray),
+            SceneItem::MovingSphere(ref s) => s.intervals(ray),
+            SceneItem::Cuboid(ref c) => c.intervals(ray),
+            SceneItem::Cylinder(ref c) => c.intervals(ray),
+            SceneItem::Cone(ref c) => c.intervals(ray),
+            SceneItem::Torus(ref t) => t.intervals(ray),
+            SceneItem::Csg(ref c) => c.intervals(ray),
+            _ => vec![]
+        }
+    }
+}
+
+// part of the ray after `t_min` that lies inside a closed boundary
+pub fn inside_span(boundary: &SceneItem, ray: &Ray, t_min: f64) -> Option<(f64, f64)> {
+    let hit = boundary.hit(ray, t_min)?;
+    let first = hit.dist;
+    // meeting the boundary from behind means the ray starts inside
+    if !boundary.surface(ray, &hit).front_face {
+        return Some((t_min, first));
+    }
+    let second = boundary.intersect(ray, first);
+    if second <= 0. {
+        return None;
+    }
+    Some((first, second))
+}
+
+impl Intersectable for ConstantMedium {
+    fn intersect(&self, ray: &Ray, t_min: f64) -> f64 {
+        let (entry, exit) = match inside_span(self.boundary(), ray, t_min) {
+            Some(span) => span,
+            None => return -1.
+        };
+
+        // sample free path length inside the medium
+        let ray_length = ray.direction().magn();
+        let inside = (exit - entry) * ray_length;
+        let random: f64 = rand::thread_rng().gen();
+        let hit_distance = -(1. - random).ln() / self.density();
+        if hit_distance > inside {
+            return -1.;
+        }
+        entry + hit_distance / ray_length
+    }
+
+    fn surface(&self, ray: &Ray, hit: &Hit) -> SurfaceInfo {
+        medium_surface(ray.point_at(hit.dist))
+    }
+}
+
+// media have no surface, the frame is arbitrary
+fn medium_surface(point: Vec3) -> SurfaceInfo {
+    SurfaceInfo {
+        point,
+        normal: Vec3::new(1., 0., 0.),
+        coords: TextureCoords::new(0., 0.),
+        dpdu: Vec3::zero(),
+        dpdv: Vec3::zero(),
+        dndu: Vec3::zero(),
+        dndv: Vec3::zero(),
+        tangent: Vec3::new(0., 1., 0.),
+        bitangent: Vec3::new(0., 0., 1.),
+        front_face: true
+    }
+}
+
+impl Intersectable for HeterogeneousMedium {
+    // delta tracking: tentative collisions against the majorant, accepted with density / max_density
+    fn intersect(&self, ray: &Ray, t_min: f64) -> f64 {
+        let (entry, exit) = match inside_span(self.boundary(), ray, t_min) {
+            Some(span) => span,
+            None => return -1.
+        };
+        if self.max_density() <= 0. {
+            return -1.;
+        }
+        let ray_length = ray.direction().magn();
+        let mut rng = rand::thread_rng();
+        let mut t = entry;
+        loop {
+            t += -(1. - rng.gen::<f64>()).ln() / (self.max_density() * ray_length);
+            if t >= exit {
+                return -1.;
+            }
+            if rng.gen::<f64>() * self.max_density() < self.density(&ray.point_at(t)) {
+                return t;
+            }
+        }
+    }
+
+    fn surface(&self, ray: &Ray, hit: &Hit) -> SurfaceInfo {
+        medium_surface(ray.point_at(hit.dist))
+    }
+}
+
 impl Intersectable for SceneItem {
-    fn intersect(&self, ray: &Ray) -> f64 {
+    fn intersect(&self, ray: &Ray, t_min: f64) -> f64 {
         match self {
-            SceneItem::Sphere(ref s) => s.intersect(&ray),
-            SceneItem::MovingSphere(ref s) => s.intersect(&ray)
+            SceneItem::Sphere(ref s) => s.intersect(ray, t_min),
+            SceneItem::MovingSphere(ref s) => s.intersect(ray, t_min),
+            SceneItem::ConstantMedium(ref m) => m.intersect(ray, t_min),
+            SceneItem::HeterogeneousMedium(ref m) => m.intersect(ray, t_min),
+            SceneItem::Plane(ref p) => p.intersect(ray, t_min),
+            SceneItem::Disk(ref d) => d.intersect(ray, t_min),
+            SceneItem::Rect(ref r) => r.intersect(ray, t_min),
+            SceneItem::Cuboid(ref c) => c.intersect(ray, t_min),
+            SceneItem::Cylinder(ref c) => c.intersect(ray, t_min),
+            SceneItem::Cone(ref c) => c.intersect(ray, t_min),
+            SceneItem::Torus(ref t) => t.intersect(ray, t_min),
+            SceneItem::Csg(ref c) => c.intersect(ray, t_min),
+            SceneItem::Sdf(ref s) => s.intersect(ray, t_min),
+            SceneItem::Heightfield(ref h) => h.intersect(ray, t_min),
+            SceneItem::Curve(ref c) => c.intersect(ray, t_min),
+            SceneItem::Animated(ref a) => a.intersect(ray, t_min),
+            SceneItem::Triangle(ref t) => t.intersect(ray, t_min),
+            SceneItem::Quadric(ref q) => q.intersect(ray, t_min),
+            SceneItem::Implicit(ref i) => i.intersect(ray, t_min),
+            SceneItem::BilinearPatch(ref p) => p.intersect(ray, t_min),
+            SceneItem::BezierPatch(ref p) => p.intersect(ray, t_min)
+        }
+    }
+
+    fn hit(&self, ray: &Ray, t_min: f64) -> Option<Hit<'_>> {
+        match self {
+            SceneItem::Sphere(ref s) => s.hit(ray, t_min),
+            SceneItem::MovingSphere(ref s) => s.hit(ray, t_min),
+            SceneItem::ConstantMedium(ref m) => m.hit(ray, t_min),
+            SceneItem::HeterogeneousMedium(ref m) => m.hit(ray, t_min),
+            SceneItem::Plane(ref p) => p.hit(ray, t_min),
+            SceneItem::Disk(ref d) => d.hit(ray, t_min),
+            SceneItem::Rect(ref r) => r.hit(ray, t_min),
+            SceneItem::Cuboid(ref c) => c.hit(ray, t_min),
+            SceneItem::Cylinder(ref c) => c.hit(ray, t_min),
+            SceneItem::Cone(ref c) => c.hit(ray, t_min),
+            SceneItem::Torus(ref t) => t.hit(ray, t_min),
+            SceneItem::Csg(ref c) => c.hit(ray, t_min),
+            SceneItem::Sdf(ref s) => s.hit(ray, t_min),
+            SceneItem::Heightfield(ref h) => h.hit(ray, t_min),
+            SceneItem::Curve(ref c) => c.hit(ray, t_min),
+            SceneItem::Animated(ref a) => a.hit(ray, t_min),
+            SceneItem::Triangle(ref t) => t.hit(ray, t_min),
+            SceneItem::Quadric(ref q) => q.hit(ray, t_min),
+            SceneItem::Implicit(ref i) => i.hit(ray, t_min),
+            SceneItem::BilinearPatch(ref p) => p.hit(ray, t_min),
+            SceneItem::BezierPatch(ref p) => p.hit(ray, t_min)
+        }
+    }
+
+    fn surface(&self, ray: &Ray, hit: &Hit) -> SurfaceInfo {
+        match self {
+            SceneItem::Sphere(ref s) => s.surface(ray, hit),
+            SceneItem::MovingSphere(ref s) => s.surface(ray, hit),
+            SceneItem::ConstantMedium(ref m) => m.surface(ray, hit),
+            SceneItem::HeterogeneousMedium(ref m) => m.surface(ray, hit),
+            SceneItem::Plane(ref p) => p.surface(ray, hit),
+            SceneItem::Disk(ref d) => d.surface(ray, hit),
+            SceneItem::Rect(ref r) => r.surface(ray, hit),
+            SceneItem::Cuboid(ref c) => c.surface(ray, hit),
+            SceneItem::Cylinder(ref c) => c.surface(ray, hit),
+            SceneItem::Cone(ref c) => c.surface(ray, hit),
+            SceneItem::Torus(ref t) => t.surface(ray, hit),
+            SceneItem::Csg(ref c) => c.surface(ray, hit),
+            SceneItem::Sdf(ref s) => s.surface(ray, hit),
+            SceneItem::Heightfield(ref h) => h.surface(ray, hit),
+            SceneItem::Curve(ref c) => c.surface(ray, hit),
+            SceneItem::Animated(ref a) => a.surface(ray, hit),
+            SceneItem::Triangle(ref t) => t.surface(ray, hit),
+            SceneItem::Quadric(ref q) => q.surface(ray, hit),
+            SceneItem::Implicit(ref i) => i.surface(ray, hit),
+            SceneItem::BilinearPatch(ref p) => p.surface(ray, hit),
+            SceneItem::BezierPatch(ref p) => p.surface(ray, hit)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::{Coloration, CsgOp, CurveBasis, Disk, Material, Plane, Rect, RectPlane};
+    use crate::sdf::SdfNode;
+    use crate::color::Color;
+    use crate::aabb::AABB;
+    use crate::transform::{Quat, Transform, Keyframe};
+
+    fn medium(radius: f64, density: f64) -> ConstantMedium {
+        let boundary = SceneItem::Sphere(Sphere::new(Vec3::zero(), radius, Material::default()));
+        ConstantMedium::new(boundary, density, Coloration::Color(Color::white()))
+    }
+
+    #[test]
+    fn constant_medium_mean_free_path() {
+        let samples = 20000;
+        let fog = medium(100., 2.);
+
+        // starting inside, the free path is measured from t_min
+        let mut inside = 0.;
+        for i in 0..samples {
+            let angle = i as f64;
+            let ray = Ray::new(Vec3::zero(), Vec3::new(angle.cos(), angle.sin(), 0.5), 0.);
+            let dist = fog.intersect(&ray, T_MIN);
+            assert!(dist > T_MIN);
+            inside += (dist - T_MIN) * ray.direction().magn();
+        }
+        assert!((inside / samples as f64 - 0.5).abs() < 0.02);
+
+        // from outside it starts at the boundary, direction length does not matter
+        let mut outside = 0.;
+        for _ in 0..samples {
+            let ray = Ray::new(Vec3::new(0., 0., 200.), Vec3::new(0., 0., -4.), 0.);
+            outside += (fog.intersect(&ray, T_MIN) * 4. - 100.) / samples as f64;
+        }
+        assert!((outside - 0.5).abs() < 0.02);
+
+        // through a unit chord a ray escapes with probability exp(-density)
+        let puff = medium(0.5, 2.);
+        let ray = Ray::new(Vec3::new(0., 0., 3.), Vec3::new(0., 0., -1.), 0.);
+        let escaped = (0..samples).filter(|_| puff.intersect(&ray, T_MIN) < 0.).count();
+        assert!((escaped as f64 / samples as f64 - (-2f64).exp()).abs() < 0.012);
+    }
+
+    #[test]
+    fn flat_primitives_hit_at_their_plane() {
+        let ray = Ray::new(Vec3::new(0.5, 0.25, 4.), Vec3::new(0., 0., -2.), 0.);
+        let plane = Plane::new(Vec3::new(3., -1., 1.), Vec3::new(0., 0., 1.), Material::default());
+        assert!((plane.intersect(&ray, T_MIN) - 1.5).abs() < 1e-12);
+        let disk = Disk::new(Vec3::new(0., 0., 1.), Vec3::new(0., 0., -1.), 1., Material::default());
+        assert!((disk.intersect(&ray, T_MIN) - 1.5).abs() < 1e-12);
+        let rect = Rect::new(RectPlane::XY, 0., 1., 0., 1., 1., Material::default());
+        assert!((rect.intersect(&ray, T_MIN) - 1.5).abs() < 1e-12);
+
+        // the normal turns towards the ray, front_face keeps the orientation
+        let info = disk.surface(&ray, &Hit::new(1.5));
+        assert!(info.normal.z() > 0. && !info.front_face);
+
+        // outside the disk and the rectangle, behind the origin, parallel
+        let aside = Ray::new(Vec3::new(1.5, 0.25, 4.), Vec3::new(0., 0., -1.), 0.);
+        assert!(disk.intersect(&aside, T_MIN) < 0. && rect.intersect(&aside, T_MIN) < 0.);
+        assert!(plane.intersect(&aside, T_MIN) > 0.);
+        let away = Ray::new(Vec3::new(0.5, 0.25, 4.), Vec3::new(0., 0., 1.), 0.);
+        assert!(plane.intersect(&away, T_MIN) < 0.);
+        let parallel = Ray::new(Vec3::new(0.5, 0.25, 4.), Vec3::new(1., 0., 0.), 0.);
+        assert!(plane.intersect(&parallel, T_MIN) < 0.);
+    }
+
+    fn ray(origin: Vec3, direction: Vec3) -> Ray {
+        Ray::new(origin, direction, 0.)
+    }
+
+    // small deterministic generator in [0, 1) so failures reproduce
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn next(&mut self) -> f64 {
+            self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (self.0 >> 11) as f64 / (1u64 << 53) as f64
+        }
+
+        fn point(&mut self, min: Vec3, max: Vec3) -> Vec3 {
+            Vec3::new(min.x() + self.next() * (max.x() - min.x()),
+                      min.y() + self.next() * (max.y() - min.y()),
+                      min.z() + self.next() * (max.z() - min.z()))
+        }
+    }
+
+    fn assert_dist(dist: f64, expected: f64) {
+        assert!((dist - expected).abs() < 1e-6, "dist {}, expected {}", dist, expected);
+    }
+
+    #[test]
+    fn interval_combinations() {
+        let (left, right) = ([(1., 4.), (6., 7.)], [(2., 3.), (3.5, 6.5)]);
+        assert_eq!(combine_intervals(&left, &right, |l, r| CsgOp::Union.inside(l, r)), vec![(1., 7.)]);
+        assert_eq!(combine_intervals(&left, &right, |l, r| CsgOp::Intersection.inside(l, r)), vec![(2., 3.), (3.5, 4.), (6., 6.5)]);
+        assert_eq!(combine_intervals(&left, &right, |l, r| CsgOp::Difference.inside(l, r)), vec![(1., 2.), (3., 3.5), (6.5, 7.)]);
+        assert!(combine_intervals(&left, &[], |l, r| CsgOp::Intersection.inside(l, r)).is_empty());
+    }
+
+    #[test]
+    fn csg_distances() {
+        let csg = |op| Csg::new(op,
+            SceneItem::Sphere(Sphere::new(Vec3::zero(), 1., Material::default())),
+            SceneItem::Sphere(Sphere::new(Vec3::new(1., 0., 0.), 1., Material::default())),
+            Material::default());
+        // the spheres cover [2, 4] and [3, 5] along this ray
+        let along = ray(Vec3::new(-3., 0., 0.), Vec3::new(1., 0., 0.));
+        let hits = |csg: &Csg| {
+            let first = csg.intersect(&along, T_MIN);
+            (first, csg.intersect(&along, first + T_MIN))
+        };
+        let (first, second) = hits(&csg(CsgOp::Union));
+        assert_dist(first, 2.);
+        assert_dist(second, 5.);
+        let (first, second) = hits(&csg(CsgOp::Intersection));
+        assert_dist(first, 3.);
+        assert_dist(second, 4.);
+        let difference = csg(CsgOp::Difference);
+        let (first, second) = hits(&difference);
+        assert_dist(first, 2.);
+        assert_dist(second, 3.);
+        // leaving through the carved out face, the normal points into the removed sphere
+        let exit = difference.surface(&along, &Hit::new(second));
+        assert!(exit.normal.x() > 0.99 && !exit.front_face);
+        assert!(difference.intersect(&along, second + T_MIN) < 0.);
+    }
+
+    #[test]
+    fn sdf_hits_after_t_min() {
+        let sphere = |material| SdfObject::new(SdfNode::Sphere { center: Vec3::zero(), radius: 1. },
+            AABB::new(Vec3::new(-2., -2., -2.), Vec3::new(2., 2., 2.)), material);
+        let along = ray(Vec3::new(-3., 0., 0.), Vec3::new(1., 0., 0.));
+        let solid = sphere(Material::default());
+        let first = solid.intersect(&along, T_MIN);
+        assert!((first - 2.).abs() < 1e-3);
+        assert!(solid.intersect(&along, first) > first);
+
+        // a fully cut out sphere lets the ray through both walls
+        let cut_out = sphere(Material { opacity: Some(Coloration::Color(Color::black())), ..Material::default() });
+        assert!(closest_hit(&SceneItem::Sdf(cut_out), &along, T_MIN).is_none());
+    }
+
+    #[test]
+    fn heightfield_walk_matches_brute_force() {
+        let mut rng = Lcg(7);
+        let (nx, nz) = (9, 7);
+        let heights = (0..nx * nz).map(|_| rng.next()).collect();
+        let bounds = AABB::new(Vec3::new(-2., 0., -1.), Vec3::new(2., 1., 1.5));
+        let field = Heightfield::new(nx, nz, heights, bounds, Material::default());
+        let mut hits = 0;
+        for _ in 0..2000 {
+            let origin = rng.point(Vec3::new(-4., -0.5, -3.), Vec3::new(4., 3., 3.5));
+            let target = rng.point(bounds.min(), bounds.max());
+            let along = ray(origin, target - origin);
+            let brute = (0..nx - 1)
+                .flat_map(|i| (0..nz - 1).map(move |j| (i, j)))
+                .filter_map(|(i, j)| field.cell_intersect(i, j, &along, T_MIN))
+                .fold(f64::MAX, f64::min);
+            let walked = field.intersect(&along, T_MIN);
+            if brute == f64::MAX {
+                assert!(walked < 0., "walk hit at {} where no triangle is hit", walked);
+            } else {
+                hits += 1;
+                assert!((walked - brute).abs() < 1e-9, "walk hit at {}, triangles at {}", walked, brute);
+            }
+        }
+        assert!(hits > 500);
+    }
+
+    #[test]
+    fn curve_hits_after_t_min() {
+        let points = [Vec3::new(-1., 0., 0.), Vec3::new(-0.3, 0.2, 0.), Vec3::new(0.3, -0.2, 0.), Vec3::new(1., 0., 0.)];
+        let curve = Curve::new(CurveBasis::Bezier, points, 0.1, 0.1, CurveShape::Flat, Material::default());
+        let along = ray(Vec3::new(0.01, 0., -2.), Vec3::new(0., 0., 1.));
+        let first = curve.intersect(&along, T_MIN);
+        assert!((first - 2.).abs() < 1e-3);
+        // a ribbon is crossed once, asking again from the hit finds nothing
+        assert!(curve.intersect(&along, first) < 0.);
+
+        // the surface takes its coordinates from the hit, near the middle of the curve
+        let hit = curve.hit(&along, T_MIN).unwrap();
+        let (u, v) = hit.uv.unwrap();
+        assert!((u - 0.5).abs() < 0.05 && (v - 0.5).abs() < 0.25, "uv ({}, {})", u, v);
+        let surface = curve.surface(&along, &hit);
+        assert_eq!((surface.coords.u, surface.coords.v), (u, v));
+    }
+
+    #[test]
+    fn animated_groups_report_the_item_hit() {
+        let glossy = Material { albedo: 0.25, ..Material::default() };
+        let group = Animated::new(vec![
+            SceneItem::Sphere(Sphere::new(Vec3::new(0., 0., 0.), 0.5, Material::default())),
+            SceneItem::Sphere(Sphere::new(Vec3::new(2., 0., 0.), 0.5, glossy))
+        ], vec![
+            Keyframe { time: 0., transform: Transform::identity() },
+            Keyframe { time: 1., transform: Transform::translation(Vec3::new(0., 1., 0.)) }
+        ]);
+        let item = SceneItem::Animated(group);
+        // halfway through the shutter the group has moved up by a half
+        let down = Ray::new(Vec3::new(2., 5., 0.), Vec3::new(0., -1., 0.), 0.5);
+        let hit = item.hit(&down, T_MIN).expect("the second sphere");
+        assert_dist(hit.dist, 4.);
+        assert_eq!(item.material(&hit).albedo, 0.25);
+        let surface = item.surface(&down, &hit);
+        assert!((surface.normal - Vec3::new(0., 1., 0.)).magn() < 1e-9 && surface.front_face);
+    }
+
+    #[test]
+    fn animated_normal_derivatives() {
+        let rotation = Quat::from_axis_angle(Vec3::new(1., 1., 0.), 30.);
+        let transform = Transform::new(Vec3::new(0.5, 0., 0.), rotation, Vec3::new(2., 0.5, 1.));
+        let ellipsoid = Animated::new(vec![SceneItem::Sphere(Sphere::new(Vec3::zero(), 1., Material::default()))],
+            vec![Keyframe { time: 0., transform }]);
+        let hit = |target: Vec3| {
+            let along = ray(Vec3::new(0.3, 0.2, -5.), target - Vec3::new(0.3, 0.2, -5.));
+            ellipsoid.surface(&along, &ellipsoid.hit(&along, T_MIN).unwrap())
+        };
+        let base = hit(Vec3::new(0.6, 0.1, 0.));
+        // step a little along the surface, the normal has to follow dndu and dndv
+        for offset in &[Vec3::new(1e-4, 0., 0.), Vec3::new(0., 1e-4, 0.)] {
+            let moved = hit(Vec3::new(0.6, 0.1, 0.) + *offset);
+            let dp = moved.point - base.point;
+            // solve dp = du * dpdu + dv * dpdv in the least squares sense
+            let (uu, uv, vv) = (base.dpdu.dot(&base.dpdu), base.dpdu.dot(&base.dpdv), base.dpdv.dot(&base.dpdv));
+            let (pu, pv) = (dp.dot(&base.dpdu), dp.dot(&base.dpdv));
+            let det = uu * vv - uv * uv;
+            let (du, dv) = ((pu * vv - pv * uv) / det, (pv * uu - pu * uv) / det);
+            let predicted = du * base.dndu + dv * base.dndv;
+            let actual = moved.normal - base.normal;
+            assert!((predicted - actual).magn() < 1e-2 * actual.magn(), "predicted {:?}, actual {:?}", predicted, actual);
         }
     }
+
+    #[test]
+    fn implicit_refine_and_intersect() {
+        let bounds = AABB::new(Vec3::new(-1.5, -1.5, -1.5), Vec3::new(1.5, 1.5, 1.5));
+        let sphere = Implicit::new(|p: &Vec3| p.dot(p) - 1., bounds, Material::default());
+        let along = ray(Vec3::new(-3., 0.2, 0.), Vec3::new(2., 0., 0.));
+        // the sphere is crossed at x = -sqrt(0.96) and x = sqrt(0.96)
+        let (near, far) = ((3. - 0.96f64.sqrt()) / 2., (3. + 0.96f64.sqrt()) / 2.);
+        assert!((sphere.refine(&along, 0.9, 1.4) - near).abs() < 1e-6);
+        assert!((sphere.refine(&along, 1.4, 2.5) - far).abs() < 1e-6);
+        assert!((sphere.intersect(&along, T_MIN) - near).abs() < 1e-6);
+        assert!((sphere.intersect(&along, near + T_MIN) - far).abs() < 1e-6);
+        assert!(sphere.intersect(&along, far + T_MIN) < 0.);
+    }
+
+    #[test]
+    fn implicit_samples_ignore_ray_start() {
+        // a slab thinner than a step, between two grid points
+        let bounds = AABB::new(Vec3::new(-1., -1., -1.), Vec3::new(1., 1., 1.));
+        let slab = Implicit::with_settings(|p: &Vec3| (p.x() - 0.1).abs() - 0.001, bounds, 8, 1e-6, Material::default());
+        let along = ray(Vec3::new(-2., 0., 0.), Vec3::new(1., 0., 0.));
+        let first = slab.intersect(&along, T_MIN);
+        for i in 1..20 {
+            let t_min = 1. + 0.05 * i as f64;
+            assert_eq!(slab.intersect(&along, t_min), first, "starting at {}", t_min);
+        }
+    }
+
+    #[test]
+    fn planar_bilinear_matches_triangles() {
+        let mut rng = Lcg(11);
+        let (p00, p10, p01, p11) = (Vec3::new(-1., 0., -1.), Vec3::new(1.2, 0., -0.8), Vec3::new(-0.7, 0., 1.), Vec3::new(0.9, 0., 1.3));
+        let mut hits = 0;
+        for _ in 0..2000 {
+            let origin = rng.point(Vec3::new(-2., 0.5, -2.), Vec3::new(2., 3., 2.));
+            let target = rng.point(Vec3::new(-1.5, 0., -1.5), Vec3::new(1.5, 0., 1.5));
+            let along = ray(origin, target - origin);
+            let patch = bilinear_intersect(p00, p10, p01, p11, &along, T_MIN).map(|hit| hit.0);
+            let triangles = triangle_intersect(p00, p10, p11, &along, T_MIN)
+                .or_else(|| triangle_intersect(p00, p11, p01, &along, T_MIN))
+                .map(|hit| hit.0);
+            match (patch, triangles) {
+                (Some(a), Some(b)) => {
+                    hits += 1;
+                    assert!((a - b).abs() < 1e-9, "patch hit at {}, triangles at {}", a, b);
+                }
+                (None, None) => {}
+                (a, b) => panic!("patch hit {:?}, triangles hit {:?}", a, b)
+            }
+        }
+        assert!(hits > 500);
+    }
+
+    #[test]
+    fn bezier_patch_covers_its_footprint() {
+        let mut rng = Lcg(5);
+        let mut points = [[Vec3::zero(); 4]; 4];
+        for (i, row) in points.iter_mut().enumerate() {
+            for (j, p) in row.iter_mut().enumerate() {
+                *p = Vec3::new(j as f64, rng.next() - 0.5, i as f64);
+            }
+        }
+        let patch = BezierPatch::new(points, Material::default());
+        for _ in 0..20000 {
+            let (u, v) = (0.02 + 0.96 * rng.next(), 0.02 + 0.96 * rng.next());
+            let (target, _, _) = bezier_patch_eval(&points, u, v);
+            // steep enough that every ray crosses the height field shaped patch once
+            let origin = target + Vec3::new(rng.next() - 0.5, 3., rng.next() - 0.5);
+            let along = ray(origin, target - origin);
+            let hit = patch.hit(&along, T_MIN).unwrap_or_else(|| panic!("aimed at ({}, {}), missed", u, v));
+            assert!((hit.dist - 1.).abs() * along.direction().magn() < 1e-3, "aimed at ({}, {}), hit at {}", u, v, hit.dist);
+            // the surface takes its coordinates from the hit
+            let (hu, hv) = hit.uv.unwrap();
+            assert!((hu - u).abs() < 1e-3 && (hv - v).abs() < 1e-3, "aimed at ({}, {}), hit ({}, {})", u, v, hu, hv);
+            let coords = patch.surface(&along, &hit).coords;
+            assert_eq!((coords.u, coords.v), (hu, hv));
+        }
+    }
+
+    #[test]
+    fn torus_distances() {
+        let torus = Torus::new(Vec3::zero(), Vec3::new(0., 1., 0.), 1., 0.25, Material::default());
+        // across the ring, first the outer then the inner wall
+        let across = ray(Vec3::new(-3., 0., 0.), Vec3::new(1., 0., 0.));
+        assert_dist(torus.intersect(&across, T_MIN), 1.75);
+        assert_dist(torus.intersect(&across, 1.75 + T_MIN), 2.25);
+        // down the tube and through the hole
+        assert_dist(torus.intersect(&ray(Vec3::new(1., 3., 0.), Vec3::new(0., -1., 0.)), T_MIN), 2.75);
+        assert!(torus.intersect(&ray(Vec3::new(0., 3., 0.), Vec3::new(0., -1., 0.)), T_MIN) < 0.);
+    }
 }
@@ -0,0 +1,15 @@
+pub mod ray;
+pub mod vector;
+pub mod intersectable;
+pub mod scene;
+pub mod camera;
+pub mod render;
+pub mod color;
+pub mod aabb;
+pub mod texture;
+pub mod volume;
+pub mod spectrum;
+pub mod bsdf;
+pub mod poly;
+pub mod sdf;
+pub mod transform;
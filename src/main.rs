@@ -1,27 +1,21 @@
 use image::{DynamicImage, GenericImage};
 use rand::Rng;
 use pbr::ProgressBar;
-pub mod ray;
-pub mod vector;
-pub mod intersectable;
-pub mod scene;
-pub mod camera;
-pub mod render;
-pub mod color;
-pub mod aabb;
-pub mod texture;
-
-use vector::Vec3;
-use scene::{Scene, Sphere, MovingSphere, Material, Surface, SceneItem, Coloration};
-use camera::Camera;
-use render::get_color;
-use color::Color;
-use texture::{CheckerTexture, NoiseTexture};
+use std::env;
+
+use raycaster::vector::Vec3;
+use raycaster::scene::{Scene, Sphere, MovingSphere, Plane, Material, Surface, SceneItem, Coloration};
+use raycaster::camera::Camera;
+use raycaster::render::{get_color, get_color_spectral};
+use raycaster::color::Color;
+use raycaster::texture::{CheckerTexture, NoiseTexture};
 
 pub fn main() {
     let nx = 600;
     let ny = 300;
     let ns = 100;
+    // dispersive materials only split light when traced spectrally
+    let spectral = env::args().any(|arg| arg == "--spectral");
 
     let mut rng = rand::thread_rng();
     let mut progress = ProgressBar::new(nx as u64);
@@ -29,11 +23,12 @@ pub fn main() {
     let camera_pos = Vec3::new(10., 4., 4.);
     let camera_look_at = Vec3::new(0., 0., 0.);
     let focus_dist = (camera_pos - camera_look_at).magn();
-    let camera = Camera::new(
+    let mut camera = Camera::new(
         camera_pos,
         camera_look_at,
         Vec3::new(0., 1., 0.),
         30., nx as f32 / ny as f32, 0.01, focus_dist, 0., 1.);
+    camera.set_resolution(nx, ny);
     let mut img = DynamicImage::new_rgb8(nx, ny);
 
     let scene = get_scene();
@@ -48,7 +43,7 @@ pub fn main() {
                 let v = (ny as f64 - y as f64 + rv) as f64 / ny as f64;
 
                 let r = camera.get_ray(u, v);
-                col = col + get_color(&scene, &r, 1);
+                col = col + if spectral { get_color_spectral(&scene, &r, 1) } else { get_color(&scene, &r, 1) };
             }
 
             col = col / ns as f32;
@@ -67,36 +62,41 @@ fn get_scene() -> Scene {
     let diff_bottom_mat = Material {
         color: Coloration::NoiseTexture(tx),
         albedo: 0.3,
-        surface: Surface::Diffuse
+        surface: Surface::Diffuse,
+        ..Material::default()
     };
-    let big_sphere = Sphere::new(Vec3::new(0., -1000., -1.), 1000., diff_bottom_mat);
+    let ground = Plane::new(Vec3::new(0., 0., 0.), Vec3::new(0., 1., 0.), diff_bottom_mat);
 
     let tx2 = CheckerTexture::new(Color::red(1.), Color::green(1.));
     let diff_sm = Material {
         color: Coloration::CheckerTexture(tx2),
         albedo: 0.4,
-        surface: Surface::Diffuse
+        surface: Surface::Diffuse,
+        ..Material::default()
     };
     let sm_sphere = Sphere::new(Vec3::new(0., 2., 0.), 2., diff_sm);
 
-    let mut items = Vec::new();
-    items.push(SceneItem::Sphere(big_sphere));
-    items.push(SceneItem::Sphere(sm_sphere));
+    let items = vec![
+        SceneItem::Plane(ground),
+        SceneItem::Sphere(sm_sphere)
+    ];
 
     Scene::new(items)
 }
 
+#[allow(dead_code)]
 fn random_scene() -> Scene {
     let tx = CheckerTexture::new(Color::blue(1.), Color::red(1.));
     let diff_bottom_mat = Material {
         color: Coloration::CheckerTexture(tx),
         albedo: 0.3,
-        surface: Surface::Diffuse
+        surface: Surface::Diffuse,
+        ..Material::default()
     };
-    let big_sphere = Sphere::new(Vec3::new(0., -1000., -1.), 1000., diff_bottom_mat);
+    let ground = Plane::new(Vec3::new(0., 0., 0.), Vec3::new(0., 1., 0.), diff_bottom_mat);
 
     let mut items = Vec::new();
-    items.push(SceneItem::Sphere(big_sphere));
+    items.push(SceneItem::Plane(ground));
 
     let mut rng = rand::thread_rng();
     for a in -11..11 {
@@ -109,7 +109,8 @@ fn random_scene() -> Scene {
                     let diff_mat = Material {
                         color: Coloration::Color(Color::new(rng.gen(), rng.gen(), rng.gen())),
                         albedo: rng.gen(),
-                        surface: Surface::Diffuse
+                        surface: Surface::Diffuse,
+                        ..Material::default()
                     };
                     let sphere = MovingSphere::new(center, Vec3::new(center.x(), center.y() + rng.gen::<f64>(), center.z()), 0.2, diff_mat, 0., 1.);
                     // let sphere = Sphere::new(center, 0.2, diff_mat);
@@ -120,7 +121,8 @@ fn random_scene() -> Scene {
                         albedo: 0.8,
                         surface: Surface::Reflective {
                             reflectivity: rng.gen()
-                        }
+                        },
+                        ..Material::default()
                     };
                     let metall_sphere = Sphere::new(center, 0.2, metall_mat);
                     items.push(SceneItem::Sphere(metall_sphere));
@@ -129,8 +131,11 @@ fn random_scene() -> Scene {
                         color: Coloration::Color(Color::white()),
                         albedo: 1.,
                         surface: Surface::Refractive {
-                            index: 1.5
-                        }
+                            index: 1.5,
+                            absorption: 0.,
+                            dispersion: None
+                        },
+                        ..Material::default()
                     };
                     let left_sphere = Sphere::new(center, 0.2, glass_mat);
                     items.push(SceneItem::Sphere(left_sphere));
@@ -143,14 +148,18 @@ fn random_scene() -> Scene {
                 color: Coloration::Color(Color::white()),
                 albedo: 0.8,
                 surface: Surface::Refractive {
-                    index: 1.5
-                }
+                    index: 1.5,
+                    absorption: 0.,
+                    dispersion: None
+                },
+                ..Material::default()
             });
 
     let s2 = Sphere::new(Vec3::new(-4., 1., 0.), 1., Material {
                 color: Coloration::Color(Color::new(0.4, 0.2, 0.1)),
                 albedo: 0.8,
-                surface: Surface::Diffuse
+                surface: Surface::Diffuse,
+                ..Material::default()
             });
 
     let s3 = Sphere::new(Vec3::new(4., 1., 0.), 1., Material {
@@ -158,7 +167,8 @@ fn random_scene() -> Scene {
         albedo: 0.8,
         surface: Surface::Reflective {
             reflectivity: 0.
-        }
+        },
+        ..Material::default()
     });
     items.push(SceneItem::Sphere(s1));
     items.push(SceneItem::Sphere(s2));
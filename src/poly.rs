@@ -0,0 +1,249 @@
+use std::f64::consts::PI;
+
+use crate::vector::Vec3;
+
+// real roots of polynomials with the highest coefficient first, in no particular order
+
+pub fn solve_quadratic(a: f64, b: f64, c: f64) -> Vec<f64> {
+    if a.abs() < 1e-12 {
+        if b.abs() < 1e-12 {
+            return vec![];
+        }
+        return vec![-c / b];
+    }
+    let discriminant = b * b - 4. * a * c;
+    if discriminant < 0. {
+        return vec![];
+    }
+    // avoids cancellation between b and the root
+    let q = -0.5 * (b + b.signum() * discriminant.sqrt());
+    if q == 0. {
+        return vec![0., 0.];
+    }
+    vec![q / a, c / q]
+}
+
+pub fn solve_cubic(a: f64, b: f64, c: f64, d: f64) -> Vec<f64> {
+    if a.abs() < 1e-12 {
+        return solve_quadratic(b, c, d);
+    }
+    let (a, b, c) = (b / a, c / a, d / a);
+    let q = (a * a - 3. * b) / 9.;
+    let r = (2. * a * a * a - 9. * a * b + 27. * c) / 54.;
+    if r * r < q * q * q {
+        let theta = (r / (q * q * q).sqrt()).clamp(-1., 1.).acos();
+        let s = -2. * q.sqrt();
+        return vec![
+            s * (theta / 3.).cos() - a / 3.,
+            s * ((theta + 2. * PI) / 3.).cos() - a / 3.,
+            s * ((theta - 2. * PI) / 3.).cos() - a / 3.
+        ];
+    }
+    let big_a = -r.signum() * (r.abs() + (r * r - q * q * q).sqrt()).cbrt();
+    let big_b = if big_a != 0. { q / big_a } else { 0. };
+    vec![big_a + big_b - a / 3.]
+}
+
+// Ferrari's method, roots are polished with Newton steps on the original polynomial
+pub fn solve_quartic(a: f64, b: f64, c: f64, d: f64, e: f64) -> Vec<f64> {
+    if a.abs() < 1e-12 {
+        return solve_cubic(b, c, d, e);
+    }
+    let (b, c, d, e) = (b / a, c / a, d / a, e / a);
+
+    // depressed quartic y^4 + p y^2 + q y + r with x = y - b / 4
+    let b2 = b * b;
+    let p = c - 3. * b2 / 8.;
+    let q = d - b * c / 2. + b2 * b / 8.;
+    let r = e - b * d / 4. + b2 * c / 16. - 3. * b2 * b2 / 256.;
+
+    let mut roots = Vec::with_capacity(4);
+    if q.abs() < 1e-12 {
+        // biquadratic
+        for z in solve_quadratic(1., p, r) {
+            if z >= 0. {
+                roots.push(z.sqrt());
+                roots.push(-z.sqrt());
+            }
+        }
+    } else {
+        let m = solve_cubic(1., p, p * p / 4. - r, -q * q / 8.).into_iter().fold(f64::MIN, f64::max);
+        if m <= 0. {
+            return vec![];
+        }
+        let s = (2. * m).sqrt();
+        roots.extend(solve_quadratic(1., -s, p / 2. + m + q / (2. * s)));
+        roots.extend(solve_quadratic(1., s, p / 2. + m - q / (2. * s)));
+    }
+
+    roots.into_iter().map(|y| {
+        let mut x = y - b / 4.;
+        for _ in 0..2 {
+            let f = (((x + b) * x + c) * x + d) * x + e;
+            let df = ((4. * x + 3. * b) * x + 2. * c) * x + d;
+            if df.abs() < 1e-12 {
+                break;
+            }
+            x -= f / df;
+        }
+        x
+    }).collect()
+}
+
+// cubic Bezier curves given by their four control points
+
+fn lerp(t: f64, a: Vec3, b: Vec3) -> Vec3 {
+    (1. - t) * a + t * b
+}
+
+// point and derivative of a cubic Bezier curve
+pub fn bezier_eval(cp: &[Vec3; 4], u: f64) -> (Vec3, Vec3) {
+    let a = [lerp(u, cp[0], cp[1]), lerp(u, cp[1], cp[2]), lerp(u, cp[2], cp[3])];
+    let b = [lerp(u, a[0], a[1]), lerp(u, a[1], a[2])];
+    let mut derivative = 3. * (b[1] - b[0]);
+    if derivative.magn() < 1e-12 {
+        // coincident control points at the ends
+        derivative = cp[3] - cp[0];
+    }
+    (lerp(u, b[0], b[1]), derivative)
+}
+
+// polar form of the curve, blossom(u, u, u) is the point at u
+pub fn bezier_blossom(cp: &[Vec3; 4], u0: f64, u1: f64, u2: f64) -> Vec3 {
+    let a = [lerp(u0, cp[0], cp[1]), lerp(u0, cp[1], cp[2]), lerp(u0, cp[2], cp[3])];
+    let b = [lerp(u1, a[0], a[1]), lerp(u1, a[1], a[2])];
+    lerp(u2, b[0], b[1])
+}
+
+// control points of the part of the curve between u0 and u1
+pub fn bezier_segment(cp: &[Vec3; 4], u0: f64, u1: f64) -> [Vec3; 4] {
+    [
+        bezier_blossom(cp, u0, u0, u0),
+        bezier_blossom(cp, u0, u0, u1),
+        bezier_blossom(cp, u0, u1, u1),
+        bezier_blossom(cp, u1, u1, u1)
+    ]
+}
+
+// halves of the curve at u = 0.5
+pub fn bezier_split(cp: &[Vec3; 4]) -> ([Vec3; 4], [Vec3; 4]) {
+    let mid = (cp[0] + 3. * cp[1] + 3. * cp[2] + cp[3]) / 8.;
+    (
+        [cp[0], (cp[0] + cp[1]) / 2., (cp[0] + 2. * cp[1] + cp[2]) / 4., mid],
+        [mid, (cp[1] + 2. * cp[2] + cp[3]) / 4., (cp[2] + cp[3]) / 2., cp[3]]
+    )
+}
+
+// Bezier control points of a uniform cubic B-spline segment
+pub fn bspline_to_bezier(p: &[Vec3; 4]) -> [Vec3; 4] {
+    [
+        (p[0] + 4. * p[1] + p[2]) / 6.,
+        (4. * p[1] + 2. * p[2]) / 6.,
+        (2. * p[1] + 4. * p[2]) / 6.,
+        (p[1] + 4. * p[2] + p[3]) / 6.
+    ]
+}
+
+// bicubic Bezier patches, cp[row][col] with u along each row and v across the rows
+
+// point and derivatives in u and v of a bicubic Bezier patch
+pub fn bezier_patch_eval(cp: &[[Vec3; 4]; 4], u: f64, v: f64) -> (Vec3, Vec3, Vec3) {
+    let mut points = [Vec3::zero(); 4];
+    let mut du = [Vec3::zero(); 4];
+    for (i, row) in cp.iter().enumerate() {
+        let (p, d) = bezier_eval(row, u);
+        points[i] = p;
+        du[i] = d;
+    }
+    let (point, dpdv) = bezier_eval(&points, v);
+    (point, bezier_eval(&du, v).0, dpdv)
+}
+
+// halves of the patch at u = 0.5
+pub fn bezier_patch_split_u(cp: &[[Vec3; 4]; 4]) -> ([[Vec3; 4]; 4], [[Vec3; 4]; 4]) {
+    let mut first = [[Vec3::zero(); 4]; 4];
+    let mut second = [[Vec3::zero(); 4]; 4];
+    for (i, row) in cp.iter().enumerate() {
+        let (a, b) = bezier_split(row);
+        first[i] = a;
+        second[i] = b;
+    }
+    (first, second)
+}
+
+// halves of the patch at v = 0.5
+pub fn bezier_patch_split_v(cp: &[[Vec3; 4]; 4]) -> ([[Vec3; 4]; 4], [[Vec3; 4]; 4]) {
+    let mut first = [[Vec3::zero(); 4]; 4];
+    let mut second = [[Vec3::zero(); 4]; 4];
+    for j in 0..4 {
+        let (a, b) = bezier_split(&[cp[0][j], cp[1][j], cp[2][j], cp[3][j]]);
+        for i in 0..4 {
+            first[i][j] = a[i];
+            second[i][j] = b[i];
+        }
+    }
+    (first, second)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_roots(mut roots: Vec<f64>, expected: &[f64], tolerance: f64) {
+        roots.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(roots.len(), expected.len(), "roots {:?}, expected {:?}", roots, expected);
+        for (root, want) in roots.iter().zip(expected) {
+            assert!((root - want).abs() < tolerance, "roots {:?}, expected {:?}", roots, expected);
+        }
+    }
+
+    #[test]
+    fn quadratic_roots() {
+        assert_roots(solve_quadratic(1., 1., -6.), &[-3., 2.], 1e-12);
+        assert_roots(solve_quadratic(0., 2., -4.), &[2.], 1e-12);
+        assert_roots(solve_quadratic(1., 0., 1.), &[], 0.);
+    }
+
+    #[test]
+    fn cubic_roots() {
+        assert_roots(solve_cubic(1., -6., 11., -6.), &[1., 2., 3.], 1e-9);
+        assert_roots(solve_cubic(2., -7., -5., 4.), &[-1., 0.5, 4.], 1e-9);
+        assert_roots(solve_cubic(1., 0., 0., -1.), &[1.], 1e-9);
+    }
+
+    #[test]
+    fn quartic_roots() {
+        assert_roots(solve_quartic(1., -10., 35., -50., 24.), &[1., 2., 3., 4.], 1e-9);
+        assert_roots(solve_quartic(1., 0., -5., 0., 4.), &[-2., -1., 1., 2.], 1e-9);
+        assert_roots(solve_quartic(3., -30., 105., -150., 72.), &[1., 2., 3., 4.], 1e-9);
+        assert_roots(solve_quartic(1., 0., 0., 0., 1.), &[], 0.);
+    }
+
+    #[test]
+    fn quartic_double_roots() {
+        // (x - 1)^2 (x + 2)^2, repeated roots may come back once or twice
+        let mut roots = solve_quartic(1., 2., -3., -4., 4.);
+        roots.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        roots.dedup_by(|a, b| (*a - *b).abs() < 1e-4);
+        assert_roots(roots, &[-2., 1.], 1e-4);
+    }
+
+    #[test]
+    fn bezier_patch_halves_match_the_patch() {
+        let mut cp = [[Vec3::zero(); 4]; 4];
+        for (i, row) in cp.iter_mut().enumerate() {
+            for (j, p) in row.iter_mut().enumerate() {
+                *p = Vec3::new(j as f64, ((i * 7 + j * 3) % 5) as f64 - 2., i as f64 + 0.3 * j as f64);
+            }
+        }
+        let (left, right) = bezier_patch_split_u(&cp);
+        let (bottom, top) = bezier_patch_split_v(&cp);
+        for &(s, t) in &[(0., 0.), (0.3, 0.8), (0.5, 0.5), (1., 0.25), (0.9, 1.)] {
+            let close = |a: Vec3, b: Vec3| assert!((a - b).magn() < 1e-12, "{:?} != {:?}", a, b);
+            close(bezier_patch_eval(&left, s, t).0, bezier_patch_eval(&cp, 0.5 * s, t).0);
+            close(bezier_patch_eval(&right, s, t).0, bezier_patch_eval(&cp, 0.5 + 0.5 * s, t).0);
+            close(bezier_patch_eval(&bottom, s, t).0, bezier_patch_eval(&cp, s, 0.5 * t).0);
+            close(bezier_patch_eval(&top, s, t).0, bezier_patch_eval(&cp, s, 0.5 + 0.5 * t).0);
+        }
+    }
+}
@@ -1,9 +1,19 @@
 use crate::vector::Vec3;
 
+// offset rays for neighbouring pixels in x and y
+#[derive(Copy, Clone, Debug)]
+pub struct RayDifferential {
+    pub rx_origin: Vec3,
+    pub rx_direction: Vec3,
+    pub ry_origin: Vec3,
+    pub ry_direction: Vec3
+}
+
 pub struct Ray {
     origin: Vec3,
     direction: Vec3,
-    time: f64
+    time: f64,
+    differential: Option<RayDifferential>
 }
 
 impl Ray {
@@ -12,18 +22,26 @@ impl Ray {
     }
 
     pub fn direction(&self) -> Vec3 {
-        self.direction.clone()
+        self.direction
     }
 
     pub fn origin(&self) -> Vec3 {
-        self.origin.clone()
+        self.origin
     }
 
     pub fn time(&self) -> f64 {
         self.time
     }
 
+    pub fn differential(&self) -> Option<&RayDifferential> {
+        self.differential.as_ref()
+    }
+
     pub fn new(origin: Vec3, direction: Vec3, time: f64) -> Ray {
-        Ray { origin, direction, time }
+        Ray { origin, direction, time, differential: None }
     }
-}
\ No newline at end of file
+
+    pub fn with_differential(origin: Vec3, direction: Vec3, time: f64, differential: Option<RayDifferential>) -> Ray {
+        Ray { origin, direction, time, differential }
+    }
+}
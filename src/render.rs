@@ -1,72 +1,343 @@
-use crate::scene::{Scene, Surface};
-use crate::ray::Ray;
+use crate::scene::{Scene, SceneItem, Surface, Material, NormalMapping};
+use crate::ray::{Ray, RayDifferential};
 use crate::vector::Vec3;
 use crate::color::Color;
+use crate::spectrum::{Spectrum, Wavelengths};
 use crate::texture::TextureCoords;
+use crate::bsdf::{sample_ggx_visible, ggx_reflection_weight, Fiber};
+use crate::intersectable::{Intersectable, SurfaceInfo, SurfaceDifferentials, tangent_frame, T_MIN};
 
 use rand::Rng;
 
 pub fn get_color(scene: &Scene, ray: &Ray, depth: u8) -> Color {
+    let mut wavelengths = Wavelengths::Rgb;
+    let radiance = trace(scene, ray, depth, None, &mut wavelengths);
+    wavelengths.to_rgb(radiance)
+}
+
+// traces a random set of wavelengths instead of rgb, needed for dispersion
+pub fn get_color_spectral(scene: &Scene, ray: &Ray, depth: u8) -> Color {
+    let mut wavelengths = Wavelengths::sample(rand::thread_rng().gen());
+    let radiance = trace(scene, ray, depth, None, &mut wavelengths);
+    wavelengths.to_rgb(radiance)
+}
+
+// `interior` is the absorption coefficient of the dielectric the ray travels through
+fn trace(scene: &Scene, ray: &Ray, depth: u8, interior: Option<Spectrum>, wavelengths: &mut Wavelengths) -> Spectrum {
     if depth > 50 {
-        return Color::black()
+        return Spectrum::zero()
     }
-    match scene.trace(&ray) {
+    match scene.trace(ray, T_MIN) {
         Some(intersection) => {
-            let material = intersection.intersected.material();
-            let normal = (ray.point_at(intersection.dist) - intersection.intersected.center(ray.time())).normalize();
-
-            match material.surface {
-                Surface::Diffuse => {
-                    let p = ray.point_at(intersection.dist);
-                    let target = normal + p + random_unit_sphere();
-                    let tex = TextureCoords {
-                        u: 0.,
-                        v: 0.
-                    };
-                    return material.albedo * material.color.color(&tex, &p) * get_color(&scene, &Ray::new(p, target - p, ray.time()), depth + 1)
-                },
-                Surface::Reflective { reflectivity } => {
-                    let reflected = reflect(ray.direction().normalize(), normal);
-                    let scattered = Ray::new(intersection.intersected.center(ray.time()), reflected + reflectivity as f64 * random_unit_sphere(), ray.time());
-                    material.albedo as f32 * get_color(&scene, &scattered, depth + 1)
-                }
-                Surface::Refractive { index } => {
-                    let outward_normal: Vec3;
-                    let reflected = reflect(ray.direction().normalize(), normal);
-                    let ni_over_nt: f32;
-                    let cosine: f32;
-
-                    if ray.direction().dot(&normal) > 0. {
-                        outward_normal = - normal;
-                        ni_over_nt = index;
-                        cosine = index * ray.direction().dot(&normal) as f32 / ray.direction().magn() as f32;
-                    } else {
-                        outward_normal = normal;
-                        ni_over_nt = 1. / index;
-                        cosine = - ray.direction().dot(&normal) as f32 / ray.direction().magn() as f32;
-                    }
+            let item = intersection.intersected;
+            let surface = item.surface(ray, &intersection.hit);
+            let differentials = surface.differentials(ray);
+            let radiance = scatter(scene, ray, item, item.material(&intersection.hit), &surface, differentials.as_ref(), depth, interior, wavelengths);
 
-                    match refract(ray.direction(), outward_normal, ni_over_nt) {
-                        Some(refracted) => {
-                            let prob = schlick(cosine, index);
-                            let mut rng = rand::thread_rng();
-                            let random: f32 = rng.gen();
-                            if random < prob {
-                                get_color(&scene, &Ray::new(intersection.intersected.center(ray.time()), reflected, ray.time()), depth + 1)
-                            } else {
-                                get_color(&scene, &Ray::new(intersection.intersected.center(ray.time()), refracted, ray.time()), depth + 1)
-                            }
-                        },
-                        None => get_color(&scene, &Ray::new(intersection.intersected.center(ray.time()), reflected, ray.time()), depth + 1)
-                    }
-                }
+            match interior {
+                Some(sigma) => beer_lambert(sigma, intersection.hit.dist * ray.direction().magn()) * radiance,
+                None => radiance
             }
         },
         None => {
             let unit = ray.direction().normalize();
             let t = 0.5 * (unit.y() + 1.0);
             let bg = Color::new(0.5, 0.7, 1.0);
-            (1.0 - t) as f32 * Color::white() + t as f32 * bg
+            wavelengths.from_rgb((1.0 - t) as f32 * Color::white() + t as f32 * bg)
+        }
+    }
+}
+
+// radiance leaving the hit point of `item` towards the ray origin, shaded with `material`
+#[allow(clippy::too_many_arguments)]
+fn scatter(scene: &Scene, ray: &Ray, item: &SceneItem, material: &Material, surface: &SurfaceInfo, differentials: Option<&SurfaceDifferentials>, depth: u8, interior: Option<Spectrum>, wavelengths: &mut Wavelengths) -> Spectrum {
+    let p = surface.point;
+    let tex = surface.texture_coords(differentials);
+    let normal = shading_normal(material, surface, &tex);
+    let albedo = |wavelengths: &Wavelengths| material.albedo * wavelengths.from_rgb(material.color.color(&tex, &p));
+
+    match material.surface {
+        Surface::Diffuse => {
+            let target = normal + p + random_unit_sphere();
+            albedo(wavelengths) * trace(scene, &Ray::new(p, target - p, ray.time()), depth + 1, interior, wavelengths)
+        },
+        Surface::Reflective { reflectivity } => {
+            let fuzz = match material.roughness {
+                Some(ref roughness) => reflectivity * roughness.value(&tex, &p),
+                None => reflectivity
+            };
+            let reflected = reflect(ray.direction().normalize(), normal);
+            let differential = reflected_differential(ray, surface, differentials, normal, reflected);
+            let scattered = Ray::with_differential(p, reflected + fuzz as f64 * random_unit_sphere(), ray.time(), differential);
+            // normal reflectance of the metal per channel
+            let base = albedo(wavelengths);
+            let reflectance = match material.thin_film {
+                Some(film) => {
+                    let cos_i = (ray.direction().dot(&normal) / ray.direction().magn()).abs() as f32;
+                    Spectrum::from_fn(|i| film.conductor_reflectance(cos_i, 1., base.value(i), wavelengths.lambda(i)))
+                },
+                None => base
+            };
+            reflectance * trace(scene, &scattered, depth + 1, interior, wavelengths)
+        }
+        Surface::Refractive { index, absorption, dispersion } => {
+            let index = match dispersion {
+                Some(dispersion) if wavelengths.is_spectral() => {
+                    // only the hero wavelength follows the dispersed path
+                    wavelengths.terminate_secondary();
+                    dispersion.ior(wavelengths.lambda(0))
+                },
+                _ => index
+            };
+            // normal on the side of the incoming ray, primitives may report either side
+            let outward_normal = if ray.direction().dot(&normal) > 0. { -normal } else { normal };
+            let reflected = reflect(ray.direction().normalize(), normal);
+            let incidence = - ray.direction().dot(&outward_normal) as f32 / ray.direction().magn() as f32;
+            let ni_over_nt: f32;
+            let cosine: f32;
+            // media on the reflected and the refracted side
+            let (reflected_interior, refracted_interior);
+            let exiting = !surface.front_face;
+
+            if exiting {
+                ni_over_nt = index;
+                cosine = index * incidence;
+                reflected_interior = interior;
+                refracted_interior = None;
+            } else {
+                ni_over_nt = 1. / index;
+                cosine = incidence;
+                reflected_interior = interior;
+                refracted_interior = absorption_coefficient(wavelengths.from_rgb(material.color.color(&tex, &p)), absorption);
+            }
+
+            let reflected_ray = || Ray::with_differential(
+                p, reflected, ray.time(),
+                reflected_differential(ray, surface, differentials, normal, reflected));
+
+            // probability to follow the reflection and path weights of both choices
+            let (prob, reflected_weight, refracted_weight) = match material.thin_film {
+                Some(film) => {
+                    let cos_i = (ray.direction().dot(&normal) / ray.direction().magn()).abs() as f32;
+                    let (outer, substrate) = if exiting { (index, 1.) } else { (1., index) };
+                    let reflectance = Spectrum::from_fn(|i| film.dielectric_reflectance(cos_i, outer, substrate, wavelengths.lambda(i)));
+                    let prob = wavelengths.average(&reflectance).clamp(1e-3, 1. - 1e-3);
+                    (prob, reflectance / prob, reflectance.map(|r| 1. - r) / (1. - prob))
+                },
+                None => (schlick(cosine, index), Spectrum::constant(1.), Spectrum::constant(1.))
+            };
+
+            match refract(ray.direction(), outward_normal, ni_over_nt) {
+                Some(refracted) => {
+                    let mut rng = rand::thread_rng();
+                    let random: f32 = rng.gen();
+                    if random < prob {
+                        reflected_weight * trace(scene, &reflected_ray(), depth + 1, reflected_interior, wavelengths)
+                    } else {
+                        let differential = refracted_differential(ray, surface, differentials, outward_normal, refracted, ni_over_nt);
+                        refracted_weight * trace(scene, &Ray::with_differential(p, refracted, ray.time(), differential), depth + 1, refracted_interior, wavelengths)
+                    }
+                },
+                None => trace(scene, &reflected_ray(), depth + 1, reflected_interior, wavelengths)
+            }
+        }
+        Surface::Isotropic => {
+            let scattered = Ray::new(p, random_unit_vector(), ray.time());
+            albedo(wavelengths) * trace(scene, &scattered, depth + 1, interior, wavelengths)
+        }
+        Surface::HenyeyGreenstein { g } => {
+            let scattered = Ray::new(p, sample_henyey_greenstein(ray.direction().normalize(), g as f64), ray.time());
+            albedo(wavelengths) * trace(scene, &scattered, depth + 1, interior, wavelengths)
+        }
+        Surface::Subsurface { mean_free_path } => {
+            let sigma_t = wavelengths.from_rgb(mean_free_path).map(|mfp| 1. / mfp.max(1e-4));
+            // primitives may turn the normal towards the ray, front_face tells which side is outside
+            let facing = ray.direction().dot(&surface.normal) < 0.;
+            let inward = if surface.front_face == facing { -surface.normal } else { surface.normal };
+            let direction = (inward + random_unit_vector()).normalize();
+            subsurface_walk(scene, item, p, direction, ray.time(), sigma_t, albedo(wavelengths), depth, interior, wavelengths)
+        }
+        Surface::Mix { ref first, ref second, ref mask } => {
+            // pick one of the materials per sample, the mask is the weight of `second`
+            let material = if rand::thread_rng().gen::<f32>() < mask.value(&tex, &p) { second } else { first };
+            scatter(scene, ray, item, material, surface, differentials, depth, interior, wavelengths)
+        }
+        Surface::Coated { ref base, index, roughness } => {
+            let cosine = (ray.direction().dot(&normal) / ray.direction().magn()).abs() as f32;
+            if rand::thread_rng().gen::<f32>() < schlick(cosine, index) {
+                let reflected = reflect(ray.direction().normalize(), normal);
+                let mut direction = reflected + roughness as f64 * random_unit_vector();
+                if direction.dot(&surface.normal) <= 0. {
+                    direction = reflected;
+                }
+                trace(scene, &Ray::new(p, direction, ray.time()), depth + 1, interior, wavelengths)
+            } else {
+                // light reaching the base passes the coat, tinted by its color
+                albedo(wavelengths) * scatter(scene, ray, item, base, surface, differentials, depth, interior, wavelengths)
+            }
+        }
+        Surface::AnisotropicMetal { roughness_u, roughness_v, ref rotation } => {
+            let scale = match material.roughness {
+                Some(ref roughness) => roughness.value(&tex, &p),
+                None => 1.
+            };
+            let ax = (roughness_u * scale).max(1e-3) as f64;
+            let ay = (roughness_v * scale).max(1e-3) as f64;
+            let angle = match rotation {
+                Some(rotation) => std::f64::consts::PI * rotation.value(&tex, &p) as f64,
+                None => 0.
+            };
+            let (t, b) = tangent_frame(&normal, &surface.dpdu);
+            let (t, b) = (angle.cos() * t + angle.sin() * b, angle.cos() * b - angle.sin() * t);
+
+            let wo = -ray.direction().normalize();
+            let local_wo = Vec3::new(wo.dot(&t), wo.dot(&b), wo.dot(&normal));
+            if local_wo.z() <= 0. {
+                return Spectrum::zero();
+            }
+            let mut rng = rand::thread_rng();
+            let m = sample_ggx_visible(local_wo, ax, ay, rng.gen(), rng.gen());
+            let local_wi = reflect(-local_wo, m);
+            if local_wi.z() <= 0. {
+                return Spectrum::zero();
+            }
+
+            let fresnel = (1. - local_wo.dot(&m).max(0.) as f32).powi(5);
+            let f0 = albedo(wavelengths);
+            let reflectance = f0.map(|f| f + (1. - f) * fresnel) * ggx_reflection_weight(local_wo, local_wi, ax, ay) as f32;
+            let wi = local_wi.x() * t + local_wi.y() * b + local_wi.z() * normal;
+            reflectance * trace(scene, &Ray::new(p, wi, ray.time()), depth + 1, interior, wavelengths)
+        }
+        Surface::Fiber { sigma_a, beta_m, beta_n, alpha, index } => {
+            // v runs across the fiber
+            let fiber = Fiber::new(2. * tex.v as f32 - 1., index, wavelengths.from_rgb(sigma_a), beta_m, beta_n, alpha);
+            let (t, b) = tangent_frame(&surface.normal, &surface.dpdu);
+            let n = surface.normal;
+            let wo = -ray.direction().normalize();
+            let local_wo = Vec3::new(wo.dot(&t), wo.dot(&b), wo.dot(&n));
+            let mut rng = rand::thread_rng();
+            match fiber.sample(local_wo, wavelengths, [rng.gen(), rng.gen(), rng.gen(), rng.gen()]) {
+                Some((local_wi, weight)) => {
+                    let wi = local_wi.x() * t + local_wi.y() * b + local_wi.z() * n;
+                    material.albedo * weight * trace(scene, &Ray::new(p, wi, ray.time()), depth + 1, interior, wavelengths)
+                },
+                None => Spectrum::zero()
+            }
+        }
+    }
+}
+
+// follows a path scattering isotropically inside `item` until it crosses the boundary again,
+// distances are sampled from a randomly picked channel and weighted by the mean pdf of all channels
+#[allow(clippy::too_many_arguments)]
+fn subsurface_walk(scene: &Scene, item: &SceneItem, entry: Vec3, direction: Vec3, time: f64, sigma_t: Spectrum, albedo: Spectrum, depth: u8, interior: Option<Spectrum>, wavelengths: &mut Wavelengths) -> Spectrum {
+    let mut rng = rand::thread_rng();
+    let mut throughput = Spectrum::constant(1.);
+    let mut origin = entry;
+    let mut direction = direction;
+
+    for _ in 0..MAX_WALK_STEPS {
+        let walk = Ray::new(origin, direction, time);
+        let boundary = match item.hit(&walk, T_MIN) {
+            Some(hit) => hit,
+            // lost the boundary through numerical error
+            None => return Spectrum::zero()
+        };
+
+        let channel = rng.gen_range(0, wavelengths.channels());
+        let dist = -(1. - rng.gen::<f32>()).ln() / sigma_t.value(channel);
+        if dist as f64 >= boundary.dist {
+            let transmittance = beer_lambert(sigma_t, boundary.dist);
+            let pdf = wavelengths.average(&transmittance);
+            if pdf <= 0. {
+                return Spectrum::zero();
+            }
+            throughput = throughput * transmittance / pdf;
+
+            // leave through a diffuse interface
+            let exit = item.surface(&walk, &boundary);
+            let outward = if direction.dot(&exit.normal) > 0. { exit.normal } else { -exit.normal };
+            let scattered = Ray::new(exit.point, (outward + random_unit_vector()).normalize(), time);
+            return throughput * trace(scene, &scattered, depth + 1, interior, wavelengths);
+        }
+
+        let transmittance = beer_lambert(sigma_t, dist as f64);
+        let pdf = wavelengths.average(&(sigma_t * transmittance));
+        if pdf <= 0. {
+            return Spectrum::zero();
+        }
+        throughput = throughput * sigma_t * transmittance * albedo / pdf;
+
+        // russian roulette once the path carries little energy
+        let survival = wavelengths.average(&throughput).min(1.);
+        if survival < 0.1 {
+            if rng.gen::<f32>() > survival {
+                return Spectrum::zero();
+            }
+            throughput = throughput / survival;
+        }
+
+        origin = walk.point_at(dist as f64);
+        direction = random_unit_vector();
+    }
+    Spectrum::zero()
+}
+
+const MAX_WALK_STEPS: u32 = 256;
+
+// `color` is what remains of white light after travelling one unit with absorption 1
+fn absorption_coefficient(color: Spectrum, absorption: f32) -> Option<Spectrum> {
+    if absorption <= 0. {
+        return None;
+    }
+    Some(color.map(|c| -c.clamp(1e-4, 1.).ln() * absorption))
+}
+
+fn beer_lambert(sigma: Spectrum, dist: f64) -> Spectrum {
+    let dist = dist as f32;
+    sigma.map(|s| (-s * dist).exp())
+}
+
+fn shading_normal(material: &Material, surface: &SurfaceInfo, tex: &TextureCoords) -> Vec3 {
+    match material.normal_mapping {
+        None => surface.normal,
+        Some(NormalMapping::Bump { ref height, scale }) => {
+            // finite differences of the height field, step from the pixel footprint when known
+            let mut du = 0.5 * (tex.dudx.abs() + tex.dudy.abs());
+            if du == 0. {
+                du = 0.0005;
+            }
+            let mut dv = 0.5 * (tex.dvdx.abs() + tex.dvdy.abs());
+            if dv == 0. {
+                dv = 0.0005;
+            }
+
+            let p = surface.point;
+            let displace = scale * height.value(tex, &p) as f64;
+            let tex_u = TextureCoords { u: tex.u + du, ..*tex };
+            let u_displace = scale * height.value(&tex_u, &(p + du * surface.dpdu)) as f64;
+            let tex_v = TextureCoords { v: tex.v + dv, ..*tex };
+            let v_displace = scale * height.value(&tex_v, &(p + dv * surface.dpdv)) as f64;
+
+            let dpdu = surface.dpdu + ((u_displace - displace) / du) * surface.normal + displace * surface.dndu;
+            let dpdv = surface.dpdv + ((v_displace - displace) / dv) * surface.normal + displace * surface.dndv;
+            let bumped = dpdu.cross(&dpdv);
+            if bumped.magn() < 1e-12 {
+                return surface.normal;
+            }
+            let bumped = bumped.normalize();
+            if bumped.dot(&surface.normal) < 0. { -bumped } else { bumped }
+        },
+        Some(NormalMapping::TangentSpace(ref map)) => {
+            let c = map.sample_footprint(tex);
+            let x = 2. * c.r() as f64 - 1.;
+            let y = 2. * c.g() as f64 - 1.;
+            let z = 2. * c.b() as f64 - 1.;
+            let mapped = x * surface.tangent + y * surface.bitangent + z * surface.normal;
+            if mapped.magn() < 1e-12 {
+                return surface.normal;
+            }
+            mapped.normalize()
         }
     }
 }
@@ -85,12 +356,89 @@ fn refract(v: Vec3, n: Vec3, ni_over_nt: f32) -> Option<Vec3> {
     }
 }
 
+// offset directions of the incoming differential and normal change across the pixel
+fn differential_terms(ray: &Ray, surface: &SurfaceInfo, diff: &SurfaceDifferentials, rd: &RayDifferential) -> (Vec3, Vec3, Vec3, Vec3, Vec3) {
+    let wo = -ray.direction().normalize();
+    let dndx = surface.dndu * diff.dudx + surface.dndv * diff.dvdx;
+    let dndy = surface.dndu * diff.dudy + surface.dndv * diff.dvdy;
+    let dwodx = -rd.rx_direction.normalize() - wo;
+    let dwody = -rd.ry_direction.normalize() - wo;
+    (wo, dndx, dndy, dwodx, dwody)
+}
+
+fn reflected_differential(ray: &Ray, surface: &SurfaceInfo, diff: Option<&SurfaceDifferentials>, n: Vec3, wi: Vec3) -> Option<RayDifferential> {
+    let rd = ray.differential()?;
+    let diff = diff?;
+    let (wo, dndx, dndy, dwodx, dwody) = differential_terms(ray, surface, diff, rd);
+    let ddndx = dwodx.dot(&n) + wo.dot(&dndx);
+    let ddndy = dwody.dot(&n) + wo.dot(&dndy);
+
+    Some(RayDifferential {
+        rx_origin: surface.point + diff.dpdx,
+        rx_direction: wi - dwodx + 2. * (wo.dot(&n) * dndx + ddndx * n),
+        ry_origin: surface.point + diff.dpdy,
+        ry_direction: wi - dwody + 2. * (wo.dot(&n) * dndy + ddndy * n)
+    })
+}
+
+// `n` faces the incoming ray, `eta` is ni / nt
+fn refracted_differential(ray: &Ray, surface: &SurfaceInfo, diff: Option<&SurfaceDifferentials>, n: Vec3, wi: Vec3, eta: f32) -> Option<RayDifferential> {
+    let rd = ray.differential()?;
+    let diff = diff?;
+    let (wo, mut dndx, mut dndy, dwodx, dwody) = differential_terms(ray, surface, diff, rd);
+    if n.dot(&surface.normal) < 0. {
+        dndx = -dndx;
+        dndy = -dndy;
+    }
+    let eta = eta as f64;
+    let ddndx = dwodx.dot(&n) + wo.dot(&dndx);
+    let ddndy = dwody.dot(&n) + wo.dot(&dndy);
+    let cos_t = wi.dot(&n).abs();
+    let mu = eta * wo.dot(&n) - cos_t;
+    let dmudx = (eta - (eta * eta * wo.dot(&n)) / cos_t) * ddndx;
+    let dmudy = (eta - (eta * eta * wo.dot(&n)) / cos_t) * ddndy;
+
+    Some(RayDifferential {
+        rx_origin: surface.point + diff.dpdx,
+        rx_direction: wi - eta * dwodx + (mu * dndx + dmudx * n),
+        ry_origin: surface.point + diff.dpdy,
+        ry_direction: wi - eta * dwody + (mu * dndy + dmudy * n)
+    })
+}
+
 fn schlick(cosine: f32, ref_ind: f32) -> f32 {
     let mut r0 = (1. - ref_ind) / (1. + ref_ind);
     r0 = r0 * r0;
     r0 + (1. - r0) * (1. - cosine).powi(5)
 }
 
+// scattered direction around the direction of travel
+fn sample_henyey_greenstein(direction: Vec3, g: f64) -> Vec3 {
+    let mut rng = rand::thread_rng();
+    let xi: f64 = rng.gen();
+    let cos_theta = if g.abs() < 1e-3 {
+        1. - 2. * xi
+    } else {
+        let sq = (1. - g * g) / (1. - g + 2. * g * xi);
+        (1. + g * g - sq * sq) / (2. * g)
+    };
+    let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+    let phi = 2. * std::f64::consts::PI * rng.gen::<f64>();
+    let (t, b) = tangent_frame(&direction, &Vec3::zero());
+    sin_theta * phi.cos() * t + sin_theta * phi.sin() * b + cos_theta * direction
+}
+
+fn random_unit_vector() -> Vec3 {
+    let mut rng = rand::thread_rng();
+    loop {
+        let p = Vec3::new(2. * rng.gen::<f64>() - 1., 2. * rng.gen::<f64>() - 1., 2. * rng.gen::<f64>() - 1.);
+        let len = p.magn();
+        if len > 1e-6 && len <= 1. {
+            return p / len;
+        }
+    }
+}
+
 fn random_unit_sphere() -> Vec3 {
     let mut p = Vec3::new(0., 0., 0.);
     let mut rng = rand::thread_rng();
@@ -99,3 +447,149 @@ fn random_unit_sphere() -> Vec3 {
     }
     p
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::{Sphere, Coloration, SceneItem, Mesh, Rect, RectPlane};
+    use crate::intersectable::T_MIN;
+    use crate::texture::{ImageTexture, TextureAddress, TextureFilter};
+    use std::sync::Arc;
+
+    fn sphere_hit() -> SurfaceInfo {
+        let sphere = Sphere::new(Vec3::zero(), 1., Material::default());
+        let ray = Ray::new(Vec3::new(0.3, 0.2, 5.), Vec3::new(0., 0., -1.), 0.);
+        sphere.surface(&ray, &sphere.hit(&ray, T_MIN).unwrap())
+    }
+
+    #[test]
+    fn flat_normal_maps_keep_the_normal() {
+        let bump = Material {
+            normal_mapping: Some(NormalMapping::Bump { height: Coloration::Color(Color::gray(0.7)), scale: 0.3 }),
+            ..Material::default()
+        };
+        let flat = ImageTexture::new(1, 1, vec![Color::new(0.5, 0.5, 1.)], TextureAddress::Wrap, TextureFilter::Nearest);
+        let tangent_space = Material { normal_mapping: Some(NormalMapping::TangentSpace(Arc::new(flat))), ..Material::default() };
+
+        let surface = sphere_hit();
+        for material in &[bump, tangent_space] {
+            let tex = surface.texture_coords(None);
+            let normal = shading_normal(material, &surface, &tex);
+            assert!((normal - surface.normal).magn() < 1e-6, "{:?} became {:?}", surface.normal, normal);
+        }
+    }
+
+    fn assert_color(c: Color, expected: Color) {
+        let close = (c.r() - expected.r()).abs() < 1e-4 && (c.g() - expected.g()).abs() < 1e-4 && (c.b() - expected.b()).abs() < 1e-4;
+        assert!(close, "{:?}, expected {:?}", c, expected);
+    }
+
+    #[test]
+    fn beer_lambert_follows_the_distance() {
+        let rgb = Wavelengths::Rgb;
+        let color = Color::new(0.5, 0.8, 1.);
+        let sigma = absorption_coefficient(rgb.from_rgb(color), 2.).unwrap();
+        // `color` remains after one unit at absorption 1, its square after twice that
+        assert_color(rgb.to_rgb(beer_lambert(sigma, 0.5)), color);
+        assert_color(rgb.to_rgb(beer_lambert(sigma, 1.)), Color::new(0.25, 0.64, 1.));
+        assert_color(rgb.to_rgb(beer_lambert(sigma, 0.)), Color::white());
+        assert!(absorption_coefficient(rgb.from_rgb(color), 0.).is_none());
+    }
+
+    #[test]
+    fn absorbing_sphere_tints_by_its_thickness() {
+        // index 1 neither bends nor reflects the central ray, which crosses two units of glass
+        let glass = Material {
+            color: Coloration::Color(Color::new(0.5, 0.8, 1.)),
+            surface: Surface::Refractive { index: 1., absorption: 1., dispersion: None },
+            ..Material::default()
+        };
+        let scene = Scene::new(vec![SceneItem::Sphere(Sphere::new(Vec3::zero(), 1., glass))]);
+        let ray = Ray::new(Vec3::new(0., 0., 5.), Vec3::new(0., 0., -1.), 0.);
+        let sky = Color::new(0.75, 0.85, 1.);
+        assert_color(get_color(&scene, &ray, 0), Color::new(0.25 * sky.r(), 0.64 * sky.g(), sky.b()));
+    }
+
+    // cube from -1 to 1 with its own vertices per face, so the smooth normals stay flat
+    fn glass_cube(material: Material) -> Vec<SceneItem> {
+        let unit = |axis: usize| Vec3::new((axis == 0) as i32 as f64, (axis == 1) as i32 as f64, (axis == 2) as i32 as f64);
+        let (mut positions, mut indices) = (vec![], vec![]);
+        for axis in 0..3 {
+            let (u, v) = (unit((axis + 1) % 3), unit((axis + 2) % 3));
+            for &sign in &[1., -1.] {
+                // counter-clockwise seen from outside
+                let corners = [-u - v, u - v, u + v, v - u];
+                let base = positions.len();
+                positions.extend(corners.iter().map(|c| sign * unit(axis) + *c));
+                indices.push(if sign > 0. { [base, base + 1, base + 2] } else { [base, base + 2, base + 1] });
+                indices.push(if sign > 0. { [base, base + 2, base + 3] } else { [base, base + 3, base + 2] });
+            }
+        }
+        Mesh::new(positions, indices, material).triangles()
+    }
+
+    #[test]
+    fn closed_meshes_refract_like_spheres() {
+        // clear index 1 keeps the path straight, a mirror behind sends it back through the glass,
+        // so the light is absorbed over four units only if both exits leave the medium
+        let glass = Material {
+            color: Coloration::Color(Color::new(0.5, 0.8, 1.)),
+            surface: Surface::Refractive { index: 1., absorption: 1., dispersion: None },
+            ..Material::default()
+        };
+        let mirror = Material { surface: Surface::Reflective { reflectivity: 0. }, ..Material::default() };
+        let back = || SceneItem::Rect(Rect::new(RectPlane::XY, -5., 5., -5., 5., -4., mirror.clone()));
+        let sky = Color::new(0.75, 0.85, 1.);
+        let expected = Color::new(0.0625 * sky.r(), 0.4096 * sky.g(), sky.b());
+
+        let sphere = Scene::new(vec![SceneItem::Sphere(Sphere::new(Vec3::zero(), 1., glass.clone())), back()]);
+        let center = Ray::new(Vec3::new(0., 0., 5.), Vec3::new(0., 0., -1.), 0.);
+        assert_color(get_color(&sphere, &center, 0), expected);
+
+        let mut items = glass_cube(glass);
+        items.push(back());
+        let cube = Scene::new(items);
+        // off the diagonals where the triangles of a face meet
+        let ray = Ray::new(Vec3::new(0.1, 0.2, 5.), Vec3::new(0., 0., -1.), 0.);
+        assert_color(get_color(&cube, &ray, 0), expected);
+    }
+
+    #[test]
+    fn henyey_greenstein_mean_cosine_is_g() {
+        let direction = Vec3::new(1., 2., -2.).normalize();
+        let samples = 40000;
+        for &g in &[-0.6, 0., 0.3, 0.85] {
+            let mut mean = 0.;
+            for _ in 0..samples {
+                let scattered = sample_henyey_greenstein(direction, g);
+                assert!((scattered.magn() - 1.).abs() < 1e-9);
+                mean += scattered.dot(&direction) / samples as f64;
+            }
+            assert!((mean - g).abs() < 0.02, "mean cosine {} for g = {}", mean, g);
+        }
+    }
+
+    #[test]
+    fn mix_mask_endpoints_pick_one_material() {
+        // a black mirror against clear glass of index 1, the central ray sees black or the sky
+        let black = Material { albedo: 0., surface: Surface::Reflective { reflectivity: 0. }, ..Material::default() };
+        let clear = Material { surface: Surface::Refractive { index: 1., absorption: 0., dispersion: None }, ..Material::default() };
+        let ray = Ray::new(Vec3::new(0., 0., 5.), Vec3::new(0., 0., -1.), 0.);
+        let sky = Color::new(0.75, 0.85, 1.);
+
+        for &(mask, expected) in &[(0., Color::black()), (1., sky)] {
+            let mixed = Material::mix(black.clone(), clear.clone(), Coloration::Color(Color::gray(mask)));
+            let scene = Scene::new(vec![SceneItem::Sphere(Sphere::new(Vec3::zero(), 1., mixed))]);
+            for _ in 0..20 {
+                assert_color(get_color(&scene, &ray, 0), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn layered_materials_keep_the_cut_outs() {
+        let holed = Material { opacity: Some(Coloration::Color(Color::black())), ..Material::default() };
+        assert!(Material::mix(holed.clone(), Material::default(), Coloration::Color(Color::gray(0.5))).opacity.is_some());
+        assert!(Material::coated(holed, 1.5, 0.).opacity.is_some());
+    }
+}
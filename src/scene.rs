@@ -1,12 +1,22 @@
 use std::f64;
-use crate::vector::Vec3;
+use std::sync::Arc;
+use std::path::Path;
+use std::cmp::Ordering;
+use image::ImageResult;
+use noise::Perlin;
+use crate::vector::{Vec3, Frame};
 use crate::ray::Ray;
 use crate::color::Color;
 use crate::aabb::{AABB, BoundingBox, surrounding_box, BVH};
-use crate::intersectable::{Intersection};
-use crate::texture::{CheckerTexture, NoiseTexture, TextureCoords};
+use crate::intersectable::{Intersection, Intersectable, Hit, closest_hit};
+use crate::volume::DensityField;
+use crate::spectrum::{Dispersion, ThinFilm};
+use crate::sdf::SdfNode;
+use crate::poly::{bezier_segment, bspline_to_bezier};
+use crate::transform::{AnimatedTransform, Keyframe, Transform};
+use crate::texture::{fbm, CheckerTexture, NoiseTexture, ImageTexture, TurbulenceTexture, MarbleTexture, WoodTexture, FbmTexture, CellularTexture, TextureCoords};
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Clone, Debug)]
 pub struct Sphere {
     center: Vec3,
     radius: f64,
@@ -15,9 +25,9 @@ pub struct Sphere {
 impl Sphere {
     pub fn new(center: Vec3, radius: f64, material: Material) -> Sphere {
         Sphere {
-            center: center,
-            radius: radius,
-            material: material
+            center,
+            radius,
+            material
         }
     }
 
@@ -29,12 +39,13 @@ impl Sphere {
         self.radius
     }
 
-    pub fn material(&self) -> Material {
-        self.material
+    pub fn material(&self) -> &Material {
+        &self.material
     }
 }
 
 impl BoundingBox for Sphere {
+    #[allow(clippy::needless_return)]
     fn bounding_box(&self) -> AABB {
         return AABB::new(
             self.center - Vec3::new(self.radius, self.radius, self.radius),
@@ -42,7 +53,7 @@ impl BoundingBox for Sphere {
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Clone, Debug)]
 pub struct MovingSphere {
     center0: Vec3,
     center1: Vec3,
@@ -56,8 +67,8 @@ impl MovingSphere {
         MovingSphere { center0, center1, t0, t1, radius, material }
     }
 
-    pub fn material(&self) -> Material {
-        self.material
+    pub fn material(&self) -> &Material {
+        &self.material
     }
 
     pub fn center(&self, time: f64) -> Vec3 {
@@ -82,23 +93,1083 @@ impl BoundingBox for MovingSphere {
     }
 }
 
+// flat primitives are two-sided, their normal faces the incoming ray
+
+// unbounded plane, kept out of the BVH, uv are distances along an arbitrary tangent frame
+#[derive(Clone, Debug)]
+pub struct Plane {
+    point: Vec3,
+    normal: Vec3,
+    material: Material
+}
+impl Plane {
+    pub fn new(point: Vec3, normal: Vec3, material: Material) -> Plane {
+        Plane { point, normal: normal.normalize(), material }
+    }
+
+    pub fn point(&self) -> Vec3 { self.point }
+
+    pub fn normal(&self) -> Vec3 { self.normal }
+
+    pub fn material(&self) -> &Material {
+        &self.material
+    }
+}
+
+impl BoundingBox for Plane {
+    // never used for the BVH, see Scene::new
+    fn bounding_box(&self) -> AABB {
+        AABB::new(Vec3::new(f64::MIN, f64::MIN, f64::MIN), Vec3::new(f64::MAX, f64::MAX, f64::MAX))
+    }
+}
+
+// u runs around the center, v from the center to the rim
+#[derive(Clone, Debug)]
+pub struct Disk {
+    center: Vec3,
+    normal: Vec3,
+    radius: f64,
+    material: Material
+}
+impl Disk {
+    pub fn new(center: Vec3, normal: Vec3, radius: f64, material: Material) -> Disk {
+        Disk { center, normal: normal.normalize(), radius, material }
+    }
+
+    pub fn center(&self) -> Vec3 { self.center }
+
+    pub fn normal(&self) -> Vec3 { self.normal }
+
+    pub fn radius(&self) -> f64 { self.radius }
+
+    pub fn material(&self) -> &Material {
+        &self.material
+    }
+}
+
+impl BoundingBox for Disk {
+    fn bounding_box(&self) -> AABB {
+        // extent of the rim along each axis
+        let n = self.normal;
+        let extent = Vec3::new(
+            self.radius * (1. - n.x() * n.x()).max(0.).sqrt() + 1e-4,
+            self.radius * (1. - n.y() * n.y()).max(0.).sqrt() + 1e-4,
+            self.radius * (1. - n.z() * n.z()).max(0.).sqrt() + 1e-4);
+        AABB::new(self.center - extent, self.center + extent)
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum RectPlane {
+    XY,
+    XZ,
+    YZ
+}
+
+impl RectPlane {
+    // indices of the two spanning axes and the constant one
+    pub fn axes(&self) -> (u8, u8, u8) {
+        match self {
+            RectPlane::XY => (0, 1, 2),
+            RectPlane::XZ => (0, 2, 1),
+            RectPlane::YZ => (1, 2, 0)
+        }
+    }
+}
+
+// axis-aligned rectangle [a0, a1] x [b0, b1] at `k` on the remaining axis, uv span it from 0 to 1
+#[derive(Clone, Debug)]
+pub struct Rect {
+    plane: RectPlane,
+    a0: f64,
+    a1: f64,
+    b0: f64,
+    b1: f64,
+    k: f64,
+    material: Material
+}
+impl Rect {
+    pub fn new(plane: RectPlane, a0: f64, a1: f64, b0: f64, b1: f64, k: f64, material: Material) -> Rect {
+        Rect { plane, a0, a1, b0, b1, k, material }
+    }
+
+    pub fn plane(&self) -> RectPlane { self.plane }
+
+    pub fn bounds(&self) -> (f64, f64, f64, f64) { (self.a0, self.a1, self.b0, self.b1) }
+
+    pub fn k(&self) -> f64 { self.k }
+
+    // point on the rectangle from its two spanning coordinates
+    pub fn point(&self, a: f64, b: f64) -> Vec3 {
+        let mut p = [0.; 3];
+        let (ia, ib, ik) = self.plane.axes();
+        p[ia as usize] = a;
+        p[ib as usize] = b;
+        p[ik as usize] = self.k;
+        Vec3::new(p[0], p[1], p[2])
+    }
+
+    pub fn material(&self) -> &Material {
+        &self.material
+    }
+}
+
+impl BoundingBox for Rect {
+    fn bounding_box(&self) -> AABB {
+        // padded so the box is not flat
+        let pad = Vec3::new(1e-4, 1e-4, 1e-4);
+        AABB::new(self.point(self.a0, self.b0) - pad, self.point(self.a1, self.b1) + pad)
+    }
+}
+
+// axis-aligned box, each face carries its own [0, 1] uv square
+#[derive(Clone, Debug)]
+pub struct Cuboid {
+    min: Vec3,
+    max: Vec3,
+    material: Material
+}
+impl Cuboid {
+    pub fn new(min: Vec3, max: Vec3, material: Material) -> Cuboid {
+        Cuboid { min, max, material }
+    }
+
+    pub fn min(&self) -> Vec3 { self.min }
+
+    pub fn max(&self) -> Vec3 { self.max }
+
+    pub fn center(&self) -> Vec3 { 0.5 * (self.min + self.max) }
+
+    pub fn material(&self) -> &Material {
+        &self.material
+    }
+}
+
+impl BoundingBox for Cuboid {
+    fn bounding_box(&self) -> AABB {
+        AABB::new(self.min, self.max)
+    }
+}
+
+// half extent along each world axis of a disk with normal `axis`
+fn disk_extent(axis: Vec3, radius: f64) -> Vec3 {
+    Vec3::new(
+        radius * (1. - axis.x() * axis.x()).max(0.).sqrt(),
+        radius * (1. - axis.y() * axis.y()).max(0.).sqrt(),
+        radius * (1. - axis.z() * axis.z()).max(0.).sqrt())
+}
+
+// cylinder from `base` to `top`, uncapped ones are open tubes and two-sided
+#[derive(Clone, Debug)]
+pub struct Cylinder {
+    frame: Frame,
+    radius: f64,
+    height: f64,
+    capped: bool,
+    material: Material
+}
+impl Cylinder {
+    pub fn new(base: Vec3, top: Vec3, radius: f64, capped: bool, material: Material) -> Cylinder {
+        Cylinder { frame: Frame::new(base, top - base), radius, height: (top - base).magn(), capped, material }
+    }
+
+    pub fn frame(&self) -> &Frame { &self.frame }
+
+    pub fn radius(&self) -> f64 { self.radius }
+
+    pub fn height(&self) -> f64 { self.height }
+
+    pub fn capped(&self) -> bool { self.capped }
+
+    pub fn material(&self) -> &Material {
+        &self.material
+    }
+}
+
+impl BoundingBox for Cylinder {
+    fn bounding_box(&self) -> AABB {
+        let extent = disk_extent(self.frame.axis(), self.radius);
+        let base = self.frame.origin();
+        let top = base + self.height * self.frame.axis();
+        surrounding_box(&AABB::new(base - extent, base + extent), &AABB::new(top - extent, top + extent))
+    }
+}
+
+// cone with its base disk around `base` narrowing to `apex`
+#[derive(Clone, Debug)]
+pub struct Cone {
+    frame: Frame,
+    radius: f64,
+    height: f64,
+    capped: bool,
+    material: Material
+}
+impl Cone {
+    pub fn new(base: Vec3, apex: Vec3, radius: f64, capped: bool, material: Material) -> Cone {
+        Cone { frame: Frame::new(base, apex - base), radius, height: (apex - base).magn(), capped, material }
+    }
+
+    pub fn frame(&self) -> &Frame { &self.frame }
+
+    pub fn radius(&self) -> f64 { self.radius }
+
+    pub fn height(&self) -> f64 { self.height }
+
+    pub fn capped(&self) -> bool { self.capped }
+
+    pub fn material(&self) -> &Material {
+        &self.material
+    }
+}
+
+impl BoundingBox for Cone {
+    fn bounding_box(&self) -> AABB {
+        let extent = disk_extent(self.frame.axis(), self.radius);
+        let base = self.frame.origin();
+        let apex = base + self.height * self.frame.axis();
+        surrounding_box(&AABB::new(base - extent, base + extent), &AABB::new(apex, apex))
+    }
+}
+
+// torus around `axis`, u runs around the axis and v around the tube
+#[derive(Clone, Debug)]
+pub struct Torus {
+    frame: Frame,
+    major_radius: f64,
+    minor_radius: f64,
+    material: Material
+}
+impl Torus {
+    pub fn new(center: Vec3, axis: Vec3, major_radius: f64, minor_radius: f64, material: Material) -> Torus {
+        Torus { frame: Frame::new(center, axis), major_radius, minor_radius, material }
+    }
+
+    pub fn frame(&self) -> &Frame { &self.frame }
+
+    pub fn major_radius(&self) -> f64 { self.major_radius }
+
+    pub fn minor_radius(&self) -> f64 { self.minor_radius }
+
+    pub fn center(&self) -> Vec3 { self.frame.origin() }
+
+    pub fn material(&self) -> &Material {
+        &self.material
+    }
+}
+
+impl BoundingBox for Torus {
+    fn bounding_box(&self) -> AABB {
+        let r = self.minor_radius;
+        let extent = disk_extent(self.frame.axis(), self.major_radius) + Vec3::new(r, r, r);
+        AABB::new(self.center() - extent, self.center() + extent)
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum CsgOp {
+    Union,
+    Intersection,
+    // left minus right
+    Difference
+}
+
+impl CsgOp {
+    pub fn inside(&self, in_left: bool, in_right: bool) -> bool {
+        match self {
+            CsgOp::Union => in_left || in_right,
+            CsgOp::Intersection => in_left && in_right,
+            CsgOp::Difference => in_left && !in_right
+        }
+    }
+}
+
+// boolean combination of two closed solids (spheres, boxes, capped cylinders and cones, tori or
+// other CSG nodes), open items contribute nothing, the whole result is shaded with `material`
+#[derive(Clone, Debug)]
+pub struct Csg {
+    op: CsgOp,
+    left: Box<SceneItem>,
+    right: Box<SceneItem>,
+    material: Material
+}
+impl Csg {
+    pub fn new(op: CsgOp, left: SceneItem, right: SceneItem, material: Material) -> Csg {
+        Csg { op, left: Box::new(left), right: Box::new(right), material }
+    }
+
+    pub fn op(&self) -> CsgOp { self.op }
+
+    pub fn left(&self) -> &SceneItem { &self.left }
+
+    pub fn right(&self) -> &SceneItem { &self.right }
+
+    pub fn material(&self) -> &Material {
+        &self.material
+    }
+}
+
+impl BoundingBox for Csg {
+    fn bounding_box(&self) -> AABB {
+        let left = self.left.bounding_box();
+        let right = self.right.bounding_box();
+        match self.op {
+            CsgOp::Union => surrounding_box(&left, &right),
+            CsgOp::Intersection => {
+                let min = Vec3::new(
+                    left.min().x().max(right.min().x()),
+                    left.min().y().max(right.min().y()),
+                    left.min().z().max(right.min().z()));
+                let max = Vec3::new(
+                    left.max().x().min(right.max().x()),
+                    left.max().y().min(right.max().y()),
+                    left.max().z().min(right.max().z()));
+                AABB::new(min, max)
+            },
+            CsgOp::Difference => left
+        }
+    }
+}
+
+// surface of a distance function found by sphere tracing inside `bounds`, hits are accepted
+// closer than `epsilon` and rays give up after `max_steps`
+#[derive(Clone, Debug)]
+pub struct SdfObject {
+    root: SdfNode,
+    bounds: AABB,
+    epsilon: f64,
+    max_steps: u32,
+    material: Material
+}
+impl SdfObject {
+    pub fn new(root: SdfNode, bounds: AABB, material: Material) -> SdfObject {
+        SdfObject { root, bounds, epsilon: 1e-4, max_steps: 256, material }
+    }
+
+    pub fn with_settings(root: SdfNode, bounds: AABB, epsilon: f64, max_steps: u32, material: Material) -> SdfObject {
+        SdfObject { root, bounds, epsilon, max_steps, material }
+    }
+
+    pub fn distance(&self, p: &Vec3) -> f64 { self.root.distance(p) }
+
+    pub fn bounds(&self) -> AABB { self.bounds }
+
+    pub fn epsilon(&self) -> f64 { self.epsilon }
+
+    pub fn max_steps(&self) -> u32 { self.max_steps }
+
+    pub fn material(&self) -> &Material {
+        &self.material
+    }
+}
+
+impl BoundingBox for SdfObject {
+    fn bounding_box(&self) -> AABB {
+        self.bounds
+    }
+}
+
+// surface a x^2 + b y^2 + c z^2 + d xy + e xz + f yz + g x + h y + i z + j = 0, optionally
+// clipped to a box, the side where it is negative counts as inside
+#[derive(Clone, Debug)]
+pub struct Quadric {
+    coefficients: [f64; 10],
+    clip: Option<AABB>,
+    // box of a closed ellipsoid, if it is one
+    ellipsoid: Option<AABB>,
+    material: Material
+}
+impl Quadric {
+    pub fn new(coefficients: [f64; 10], clip: Option<AABB>, material: Material) -> Quadric {
+        let ellipsoid = Quadric::ellipsoid_bounds(&coefficients);
+        Quadric { coefficients, clip, ellipsoid, material }
+    }
+
+    // x^2 / rx^2 + y^2 / ry^2 + z^2 / rz^2 = 1 around `center`
+    pub fn ellipsoid(center: Vec3, radii: Vec3, material: Material) -> Quadric {
+        let (a, b, c) = (1. / (radii.x() * radii.x()), 1. / (radii.y() * radii.y()), 1. / (radii.z() * radii.z()));
+        let (x, y, z) = (center.x(), center.y(), center.z());
+        Quadric::new([a, b, c, 0., 0., 0., -2. * a * x, -2. * b * y, -2. * c * z, a * x * x + b * y * y + c * z * z - 1.], None, material)
+    }
+
+    // y = (x^2 + z^2) / (4 focal) opening up from `vertex`, cut at `depth` above it
+    pub fn paraboloid(vertex: Vec3, focal: f64, depth: f64, material: Material) -> Quadric {
+        let k = 1. / (4. * focal);
+        let (x, y, z) = (vertex.x(), vertex.y(), vertex.z());
+        let radius = (depth / k).sqrt();
+        let clip = AABB::new(Vec3::new(x - radius, y, z - radius), Vec3::new(x + radius, y + depth, z + radius));
+        Quadric::new([k, 0., k, 0., 0., 0., -2. * k * x, -1., -2. * k * z, k * (x * x + z * z) + y], Some(clip), material)
+    }
+
+    // x^2 / r^2 + z^2 / r^2 - y^2 / s^2 = 1 around the y axis through `center`, of one sheet
+    // with waist `radius`, cut at `height` above and below the waist
+    pub fn hyperboloid(center: Vec3, radius: f64, slope: f64, height: f64, material: Material) -> Quadric {
+        let (a, b) = (1. / (radius * radius), 1. / (slope * slope));
+        let (x, y, z) = (center.x(), center.y(), center.z());
+        let reach = radius * (1. + height * height * b).sqrt();
+        let clip = AABB::new(Vec3::new(x - reach, y - height, z - reach), Vec3::new(x + reach, y + height, z + reach));
+        Quadric::new([a, -b, a, 0., 0., 0., -2. * a * x, 2. * b * y, -2. * a * z, a * (x * x + z * z) - b * y * y - 1.], Some(clip), material)
+    }
+
+    // box around the surface when the quadratic part is definite, from the center and the
+    // inverse of the symmetric matrix
+    fn ellipsoid_bounds(q: &[f64; 10]) -> Option<AABB> {
+        let m = [[q[0], 0.5 * q[3], 0.5 * q[4]], [0.5 * q[3], q[1], 0.5 * q[5]], [0.5 * q[4], 0.5 * q[5], q[2]]];
+        let minor = m[0][0] * m[1][1] - m[0][1] * m[1][0];
+        let det = m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0]);
+        // Sylvester's criterion, a negative definite form is flipped first
+        let sign = m[0][0].signum();
+        if m[0][0] == 0. || minor <= 0. || sign * det <= 0. {
+            return None;
+        }
+        let adjugate = |r: usize, c: usize| {
+            let (r0, r1) = ((c + 1) % 3, (c + 2) % 3);
+            let (c0, c1) = ((r + 1) % 3, (r + 2) % 3);
+            m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]
+        };
+        let inverse: Vec<Vec<f64>> = (0..3).map(|r| (0..3).map(|c| adjugate(r, c) / det).collect()).collect();
+        let g = [q[6], q[7], q[8]];
+        let center: Vec<f64> = (0..3).map(|r| -0.5 * (0..3).map(|c| inverse[r][c] * g[c]).sum::<f64>()).collect();
+        // value of the quadratic part on the surface, relative to the center
+        let k = (0..3).map(|r| (0..3).map(|c| center[r] * m[r][c] * center[c]).sum::<f64>()).sum::<f64>() - q[9];
+        if sign * k <= 0. {
+            return None;
+        }
+        let half: Vec<f64> = (0..3).map(|i| (k * inverse[i][i]).sqrt()).collect();
+        Some(AABB::new(
+            Vec3::new(center[0] - half[0], center[1] - half[1], center[2] - half[2]),
+            Vec3::new(center[0] + half[0], center[1] + half[1], center[2] + half[2])
+        ))
+    }
+
+    pub fn coefficients(&self) -> &[f64; 10] { &self.coefficients }
+
+    pub fn clip(&self) -> Option<AABB> { self.clip }
+
+    // an unclipped ellipsoid is the only closed quadric, all others turn their normal to the ray
+    pub fn is_closed(&self) -> bool {
+        self.clip.is_none() && self.ellipsoid.is_some()
+    }
+
+    pub fn is_unbounded(&self) -> bool {
+        self.clip.is_none() && self.ellipsoid.is_none()
+    }
+
+    pub fn value(&self, p: &Vec3) -> f64 {
+        let q = &self.coefficients;
+        let (x, y, z) = (p.x(), p.y(), p.z());
+        q[0] * x * x + q[1] * y * y + q[2] * z * z + q[3] * x * y + q[4] * x * z + q[5] * y * z + q[6] * x + q[7] * y + q[8] * z + q[9]
+    }
+
+    pub fn gradient(&self, p: &Vec3) -> Vec3 {
+        let q = &self.coefficients;
+        let (x, y, z) = (p.x(), p.y(), p.z());
+        Vec3::new(
+            2. * q[0] * x + q[3] * y + q[4] * z + q[6],
+            2. * q[1] * y + q[3] * x + q[5] * z + q[7],
+            2. * q[2] * z + q[4] * x + q[5] * y + q[8]
+        )
+    }
+
+    pub fn material(&self) -> &Material {
+        &self.material
+    }
+}
+
+impl BoundingBox for Quadric {
+    fn bounding_box(&self) -> AABB {
+        match (self.clip, self.ellipsoid) {
+            (Some(clip), _) => clip,
+            (None, Some(ellipsoid)) => ellipsoid,
+            // unclipped open quadrics stay out of the BVH like planes, see Scene::new
+            (None, None) => AABB::new(Vec3::new(f64::MIN, f64::MIN, f64::MIN), Vec3::new(f64::MAX, f64::MAX, f64::MAX))
+        }
+    }
+}
+
+// zero set of a user function inside `bounds`, negative inside, found by stepping along the ray
+// until the sign changes and refining the bracket with safeguarded Newton iteration
+#[derive(Clone)]
+pub struct Implicit {
+    function: Arc<dyn Fn(&Vec3) -> f64 + Send + Sync>,
+    bounds: AABB,
+    steps: u32,
+    epsilon: f64,
+    material: Material
+}
+impl Implicit {
+    pub fn new<F: Fn(&Vec3) -> f64 + Send + Sync + 'static>(function: F, bounds: AABB, material: Material) -> Implicit {
+        Implicit::with_settings(function, bounds, 256, 1e-6, material)
+    }
+
+    // `steps` samples across the bounds find sign changes, features thinner than a step can be missed
+    pub fn with_settings<F: Fn(&Vec3) -> f64 + Send + Sync + 'static>(function: F, bounds: AABB, steps: u32, epsilon: f64, material: Material) -> Implicit {
+        Implicit { function: Arc::new(function), bounds, steps: steps.max(1), epsilon, material }
+    }
+
+    pub fn value(&self, p: &Vec3) -> f64 { (self.function)(p) }
+
+    pub fn bounds(&self) -> AABB { self.bounds }
+
+    pub fn steps(&self) -> u32 { self.steps }
+
+    pub fn epsilon(&self) -> f64 { self.epsilon }
+
+    pub fn material(&self) -> &Material {
+        &self.material
+    }
+}
+
+impl std::fmt::Debug for Implicit {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Implicit")
+            .field("bounds", &self.bounds)
+            .field("steps", &self.steps)
+            .field("epsilon", &self.epsilon)
+            .field("material", &self.material)
+            .finish()
+    }
+}
+
+impl BoundingBox for Implicit {
+    fn bounding_box(&self) -> AABB {
+        self.bounds
+    }
+}
+
+// quad with straight edges between four corners that need not be coplanar, u runs from p00 to
+// p10 and v from p00 to p01
+#[derive(Clone, Debug)]
+pub struct BilinearPatch {
+    p00: Vec3,
+    p10: Vec3,
+    p01: Vec3,
+    p11: Vec3,
+    material: Material
+}
+impl BilinearPatch {
+    pub fn new(p00: Vec3, p10: Vec3, p01: Vec3, p11: Vec3, material: Material) -> BilinearPatch {
+        BilinearPatch { p00, p10, p01, p11, material }
+    }
+
+    pub fn corners(&self) -> [Vec3; 4] { [self.p00, self.p10, self.p01, self.p11] }
+
+    pub fn material(&self) -> &Material {
+        &self.material
+    }
+}
+
+impl BoundingBox for BilinearPatch {
+    fn bounding_box(&self) -> AABB {
+        points_bounds(&self.corners())
+    }
+}
+
+// bicubic Bezier patch from 4 x 4 control points, rows run along u and follow each other in v
+#[derive(Clone, Debug)]
+pub struct BezierPatch {
+    points: [[Vec3; 4]; 4],
+    material: Material
+}
+impl BezierPatch {
+    pub fn new(points: [[Vec3; 4]; 4], material: Material) -> BezierPatch {
+        BezierPatch { points, material }
+    }
+
+    pub fn points(&self) -> &[[Vec3; 4]; 4] { &self.points }
+
+    pub fn material(&self) -> &Material {
+        &self.material
+    }
+}
+
+impl BoundingBox for BezierPatch {
+    fn bounding_box(&self) -> AABB {
+        // the patch lies in the convex hull of its control points
+        points_bounds(self.points.iter().flatten())
+    }
+}
+
+// box around a set of points, padded a little so flat sets still have a volume
+pub fn points_bounds<'a, I: IntoIterator<Item = &'a Vec3>>(points: I) -> AABB {
+    let mut points = points.into_iter();
+    let first = *points.next().expect("bounds of no points");
+    let bbox = points.fold(AABB::new(first, first), |bbox, p| surrounding_box(&bbox, &AABB::new(*p, *p)));
+    let pad = 1e-6 * (1. + (bbox.max() - bbox.min()).magn());
+    AABB::new(bbox.min() - Vec3::new(pad, pad, pad), bbox.max() + Vec3::new(pad, pad, pad))
+}
+
+// terrain over the xz rectangle of `bounds`, heights in [0, 1] span its y range, rays walk the
+// grid cells and only test the two triangles of cells their height range overlaps
+#[derive(Clone, Debug)]
+pub struct Heightfield {
+    nx: usize,
+    nz: usize,
+    heights: Arc<Vec<f64>>,
+    // lowest and highest world y of each cell
+    cell_range: Arc<Vec<(f64, f64)>>,
+    bounds: AABB,
+    material: Material
+}
+impl Heightfield {
+    // `heights` holds nx * nz samples, row by row along x
+    pub fn new(nx: usize, nz: usize, heights: Vec<f64>, bounds: AABB, material: Material) -> Heightfield {
+        assert!(nx >= 2 && nz >= 2 && heights.len() == nx * nz, "heightfield needs at least 2 x 2 samples");
+        let (y0, y1) = (bounds.min().y(), bounds.max().y());
+        let heights: Vec<f64> = heights.iter().map(|h| y0 + h.clamp(0., 1.) * (y1 - y0)).collect();
+        let mut cell_range = Vec::with_capacity((nx - 1) * (nz - 1));
+        for j in 0..nz - 1 {
+            for i in 0..nx - 1 {
+                let corners = [heights[j * nx + i], heights[j * nx + i + 1], heights[(j + 1) * nx + i], heights[(j + 1) * nx + i + 1]];
+                let low = corners.iter().cloned().fold(f64::MAX, f64::min);
+                let high = corners.iter().cloned().fold(f64::MIN, f64::max);
+                cell_range.push((low, high));
+            }
+        }
+        Heightfield { nx, nz, heights: Arc::new(heights), cell_range: Arc::new(cell_range), bounds, material }
+    }
+
+    // heights from the luminance of a grayscale image, rows run along z
+    pub fn open<P: AsRef<Path>>(path: P, bounds: AABB, material: Material) -> ImageResult<Heightfield> {
+        let img = image::open(path)?.to_luma();
+        let (nx, nz) = (img.width() as usize, img.height() as usize);
+        let heights = img.pixels().map(|p| p[0] as f64 / 255.).collect();
+        Ok(Heightfield::new(nx, nz, heights, bounds, material))
+    }
+
+    // fractal Perlin noise terrain, `scale` is the number of base noise features across the field
+    pub fn from_noise(nx: usize, nz: usize, scale: f64, octaves: u8, bounds: AABB, material: Material) -> Heightfield {
+        let noise = Perlin::new();
+        let mut heights = Vec::with_capacity(nx * nz);
+        for j in 0..nz {
+            for i in 0..nx {
+                let point = Vec3::new(scale * i as f64 / (nx - 1) as f64, 0.5, scale * j as f64 / (nz - 1) as f64);
+                heights.push(0.5 + 0.5 * fbm(&point, octaves, 2., 0.5, &noise));
+            }
+        }
+        Heightfield::new(nx, nz, heights, bounds, material)
+    }
+
+    pub fn resolution(&self) -> (usize, usize) { (self.nx, self.nz) }
+
+    // world y of a grid sample
+    pub fn height(&self, i: usize, j: usize) -> f64 {
+        self.heights[j.min(self.nz - 1) * self.nx + i.min(self.nx - 1)]
+    }
+
+    pub fn cell_range(&self, i: usize, j: usize) -> (f64, f64) {
+        self.cell_range[j * (self.nx - 1) + i]
+    }
+
+    // world position of a grid sample
+    pub fn vertex(&self, i: usize, j: usize) -> Vec3 {
+        let (cw, ch) = self.cell_size();
+        Vec3::new(self.bounds.min().x() + i as f64 * cw, self.height(i, j), self.bounds.min().z() + j as f64 * ch)
+    }
+
+    pub fn cell_size(&self) -> (f64, f64) {
+        let size = self.bounds.max() - self.bounds.min();
+        (size.x() / (self.nx - 1) as f64, size.z() / (self.nz - 1) as f64)
+    }
+
+    pub fn bounds(&self) -> AABB { self.bounds }
+
+    pub fn material(&self) -> &Material {
+        &self.material
+    }
+}
+
+impl BoundingBox for Heightfield {
+    fn bounding_box(&self) -> AABB {
+        self.bounds
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum CurveBasis {
+    Bezier,
+    // uniform cubic B-spline, consecutive segments share three control points
+    BSpline
+}
+
 #[derive(Copy, Clone, Debug)]
+pub enum CurveShape {
+    // ribbon facing the ray
+    Flat,
+    // ribbon facing the ray shaded like a cylinder
+    Round
+}
+
+// piece [u0, u1] of a cubic curve with width varying linearly along it, v runs across the width
+#[derive(Clone, Debug)]
+pub struct Curve {
+    // Bezier control points of the piece
+    points: [Vec3; 4],
+    u0: f64,
+    u1: f64,
+    // width at the start and the end of the whole curve
+    width0: f64,
+    width1: f64,
+    shape: CurveShape,
+    material: Material
+}
+impl Curve {
+    pub fn new(basis: CurveBasis, points: [Vec3; 4], width0: f64, width1: f64, shape: CurveShape, material: Material) -> Curve {
+        let points = match basis {
+            CurveBasis::Bezier => points,
+            CurveBasis::BSpline => bspline_to_bezier(&points)
+        };
+        Curve { points, u0: 0., u1: 1., width0, width1, shape, material }
+    }
+
+    // long thin curves make poor BVH leaves, pieces have tighter bounds
+    pub fn split(&self, pieces: usize) -> Vec<Curve> {
+        let pieces = pieces.max(1);
+        (0..pieces).map(|i| {
+            let a = i as f64 / pieces as f64;
+            let b = (i + 1) as f64 / pieces as f64;
+            Curve {
+                points: bezier_segment(&self.points, a, b),
+                u0: self.u0 + a * (self.u1 - self.u0),
+                u1: self.u0 + b * (self.u1 - self.u0),
+                ..self.clone()
+            }
+        }).collect()
+    }
+
+    // strand through many control points (3n + 1 for Bezier, n + 3 for B-spline), the width
+    // varies along the whole strand and each segment is split into `pieces`
+    pub fn strand(basis: CurveBasis, points: &[Vec3], width0: f64, width1: f64, shape: CurveShape, pieces: usize, material: Material) -> Vec<SceneItem> {
+        let windows: Vec<[Vec3; 4]> = match basis {
+            CurveBasis::Bezier => points.windows(4).step_by(3).map(|w| [w[0], w[1], w[2], w[3]]).collect(),
+            CurveBasis::BSpline => points.windows(4).map(|w| [w[0], w[1], w[2], w[3]]).collect()
+        };
+        let segments = windows.len();
+        windows.iter().enumerate().flat_map(|(i, window)| {
+            let w0 = width0 + (width1 - width0) * i as f64 / segments as f64;
+            let w1 = width0 + (width1 - width0) * (i + 1) as f64 / segments as f64;
+            Curve::new(basis, *window, w0, w1, shape, material.clone()).split(pieces)
+        }).map(SceneItem::Curve).collect()
+    }
+
+    pub fn points(&self) -> &[Vec3; 4] { &self.points }
+
+    pub fn u_range(&self) -> (f64, f64) { (self.u0, self.u1) }
+
+    pub fn width(&self, u: f64) -> f64 {
+        self.width0 + (self.width1 - self.width0) * u
+    }
+
+    pub fn shape(&self) -> CurveShape { self.shape }
+
+    pub fn material(&self) -> &Material {
+        &self.material
+    }
+}
+
+impl BoundingBox for Curve {
+    fn bounding_box(&self) -> AABB {
+        // convex hull of the control points padded by half the width
+        let half = 0.5 * self.width(self.u0).max(self.width(self.u1));
+        let pad = Vec3::new(half, half, half);
+        let hull = self.points.iter().fold(AABB::new(self.points[0], self.points[0]), |bbox, p| surrounding_box(&bbox, &AABB::new(*p, *p)));
+        AABB::new(hull.min() - pad, hull.max() + pad)
+    }
+}
+
+// triangle mesh whose vertices may move between keyframes, positions are interpolated linearly
+// at the time of each ray so deforming geometry blurs
+#[derive(Clone, Debug)]
+pub struct Mesh {
+    times: Vec<f64>,
+    // vertex positions and smoothed vertex normals for every key
+    positions: Vec<Vec<Vec3>>,
+    normals: Vec<Vec<Vec3>>,
+    uvs: Option<Vec<TextureCoords>>,
+    indices: Vec<[usize; 3]>,
+    material: Material
+}
+impl Mesh {
+    pub fn new(positions: Vec<Vec3>, indices: Vec<[usize; 3]>, material: Material) -> Mesh {
+        Mesh::deforming(vec![(0., positions)], indices, material)
+    }
+
+    // every key holds a position for each vertex, in the same order
+    pub fn deforming(mut keys: Vec<(f64, Vec<Vec3>)>, indices: Vec<[usize; 3]>, material: Material) -> Mesh {
+        assert!(!keys.is_empty(), "mesh needs at least one key");
+        let count = keys[0].1.len();
+        assert!(keys.iter().all(|(_, p)| p.len() == count), "mesh keys need the same number of vertices");
+        assert!(indices.iter().all(|t| t.iter().all(|&i| i < count)), "mesh index out of range");
+        keys.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal));
+        let times = keys.iter().map(|(t, _)| *t).collect();
+        let positions: Vec<Vec<Vec3>> = keys.into_iter().map(|(_, p)| p).collect();
+        let normals = positions.iter().map(|p| Mesh::vertex_normals(p, &indices)).collect();
+        Mesh { times, positions, normals, uvs: None, indices, material }
+    }
+
+    pub fn with_uvs(mut self, uvs: Vec<TextureCoords>) -> Mesh {
+        assert!(uvs.len() == self.positions[0].len(), "mesh needs one uv per vertex");
+        self.uvs = Some(uvs);
+        self
+    }
+
+    // area weighted average of the faces around each vertex
+    fn vertex_normals(positions: &[Vec3], indices: &[[usize; 3]]) -> Vec<Vec3> {
+        let mut normals = vec![Vec3::zero(); positions.len()];
+        for &[a, b, c] in indices {
+            let face = (positions[b] - positions[a]).cross(&(positions[c] - positions[a]));
+            for &i in &[a, b, c] {
+                normals[i] = normals[i] + face;
+            }
+        }
+        normals.iter().map(|n| if n.magn() > 0. { n.normalize() } else { *n }).collect()
+    }
+
+    // one item per triangle so the BVH can separate them
+    pub fn triangles(self) -> Vec<SceneItem> {
+        let mesh = Arc::new(self);
+        (0..mesh.indices.len()).map(|index| SceneItem::Triangle(Triangle { mesh: mesh.clone(), index })).collect()
+    }
+
+    // keys around `time` and the blend between them
+    fn keys_at(&self, time: f64) -> (usize, usize, f64) {
+        let last = self.times.len() - 1;
+        if time <= self.times[0] {
+            return (0, 0, 0.);
+        }
+        if time >= self.times[last] {
+            return (last, last, 0.);
+        }
+        let next = self.times.iter().position(|&t| t > time).unwrap();
+        (next - 1, next, (time - self.times[next - 1]) / (self.times[next] - self.times[next - 1]))
+    }
+
+    pub fn vertex(&self, i: usize, time: f64) -> Vec3 {
+        let (a, b, t) = self.keys_at(time);
+        self.positions[a][i] + t * (self.positions[b][i] - self.positions[a][i])
+    }
+
+    pub fn normal(&self, i: usize, time: f64) -> Vec3 {
+        let (a, b, t) = self.keys_at(time);
+        self.normals[a][i] + t * (self.normals[b][i] - self.normals[a][i])
+    }
+
+    pub fn uv(&self, i: usize) -> Option<TextureCoords> {
+        self.uvs.as_ref().map(|uvs| uvs[i])
+    }
+
+    pub fn material(&self) -> &Material {
+        &self.material
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Triangle {
+    mesh: Arc<Mesh>,
+    index: usize
+}
+impl Triangle {
+    pub fn mesh(&self) -> &Mesh { &self.mesh }
+
+    pub fn indices(&self) -> [usize; 3] { self.mesh.indices[self.index] }
+
+    pub fn vertices(&self, time: f64) -> [Vec3; 3] {
+        let [a, b, c] = self.indices();
+        [self.mesh.vertex(a, time), self.mesh.vertex(b, time), self.mesh.vertex(c, time)]
+    }
+
+    pub fn material(&self) -> &Material {
+        self.mesh.material()
+    }
+}
+
+impl BoundingBox for Triangle {
+    fn bounding_box(&self) -> AABB {
+        // vertices move linearly between keys, so the corners at every key bound the motion
+        let first = self.mesh.positions[0][self.indices()[0]];
+        self.mesh.positions.iter().flat_map(|key| self.indices().iter().map(move |&i| key[i]).collect::<Vec<_>>())
+            .fold(AABB::new(first, first), |bbox, p| surrounding_box(&bbox, &AABB::new(p, p)))
+    }
+}
+
+// items moved together by keyframed transforms over the shutter, evaluated at the time of each
+// ray, they keep their own BVH in local space
+#[derive(Clone, Debug)]
+pub struct Animated {
+    items: Box<Scene>,
+    motion: AnimatedTransform
+}
+impl Animated {
+    pub fn new(items: Vec<SceneItem>, keys: Vec<Keyframe>) -> Animated {
+        assert!(!items.is_empty(), "animated group needs at least one item");
+        Animated { items: Box::new(Scene::new(items)), motion: AnimatedTransform::new(keys) }
+    }
+
+    pub fn items(&self) -> &Scene { &self.items }
+
+    pub fn transform(&self, time: f64) -> Transform { self.motion.at(time) }
+}
+
+impl BoundingBox for Animated {
+    fn bounding_box(&self) -> AABB {
+        match self.items.bounding_box() {
+            Some(bbox) => self.motion.motion_bounds(&bbox),
+            // only unbounded items, never used for the BVH
+            None => AABB::new(Vec3::new(f64::MIN, f64::MIN, f64::MIN), Vec3::new(f64::MAX, f64::MAX, f64::MAX))
+        }
+    }
+}
+
+// fog or smoke filling a closed boundary, scatters isotropically
+#[derive(Clone, Debug)]
+pub struct ConstantMedium {
+    boundary: Box<SceneItem>,
+    density: f64,
+    material: Material
+}
+impl ConstantMedium {
+    pub fn new(boundary: SceneItem, density: f64, albedo: Coloration) -> ConstantMedium {
+        ConstantMedium {
+            boundary: Box::new(boundary),
+            density,
+            material: Material {
+                color: albedo,
+                surface: Surface::Isotropic,
+                ..Material::default()
+            }
+        }
+    }
+
+    pub fn boundary(&self) -> &SceneItem { &self.boundary }
+
+    pub fn density(&self) -> f64 { self.density }
+
+    pub fn material(&self) -> &Material {
+        &self.material
+    }
+}
+
+impl BoundingBox for ConstantMedium {
+    fn bounding_box(&self) -> AABB {
+        self.boundary.bounding_box()
+    }
+}
+
+// medium with density varying in space, traced with delta tracking against `max_density`
+#[derive(Clone, Debug)]
+pub struct HeterogeneousMedium {
+    boundary: Box<SceneItem>,
+    field: DensityField,
+    max_density: f64,
+    material: Material
+}
+impl HeterogeneousMedium {
+    // `g` is the Henyey-Greenstein anisotropy, positive for forward scattering
+    pub fn new(boundary: SceneItem, field: DensityField, max_density: f64, albedo: Coloration, g: f32) -> HeterogeneousMedium {
+        HeterogeneousMedium {
+            boundary: Box::new(boundary),
+            field,
+            max_density,
+            material: Material {
+                color: albedo,
+                surface: Surface::HenyeyGreenstein { g },
+                ..Material::default()
+            }
+        }
+    }
+
+    pub fn boundary(&self) -> &SceneItem { &self.boundary }
+
+    pub fn max_density(&self) -> f64 { self.max_density }
+
+    pub fn density(&self, point: &Vec3) -> f64 {
+        self.max_density * self.field.density(point)
+    }
+
+    pub fn material(&self) -> &Material {
+        &self.material
+    }
+}
+
+impl BoundingBox for HeterogeneousMedium {
+    fn bounding_box(&self) -> AABB {
+        self.boundary.bounding_box()
+    }
+}
+
+#[derive(Clone, Debug)]
 pub enum SceneItem {
     Sphere(Sphere),
-    MovingSphere(MovingSphere)
+    MovingSphere(MovingSphere),
+    ConstantMedium(ConstantMedium),
+    HeterogeneousMedium(HeterogeneousMedium),
+    Plane(Plane),
+    Disk(Disk),
+    Rect(Rect),
+    Cuboid(Cuboid),
+    Cylinder(Cylinder),
+    Cone(Cone),
+    Torus(Torus),
+    Csg(Csg),
+    Sdf(SdfObject),
+    Heightfield(Heightfield),
+    Curve(Curve),
+    Animated(Animated),
+    Triangle(Triangle),
+    Quadric(Quadric),
+    Implicit(Implicit),
+    BilinearPatch(BilinearPatch),
+    BezierPatch(BezierPatch)
 }
 impl SceneItem {
-    pub fn material(&self) -> Material {
+    // material at a hit, animated groups take it from the item that was hit
+    pub fn material<'a>(&'a self, hit: &'a Hit<'a>) -> &'a Material {
         match self {
             SceneItem::Sphere(ref s) => s.material(),
-            SceneItem::MovingSphere(ref s) => s.material()
+            SceneItem::MovingSphere(ref s) => s.material(),
+            SceneItem::ConstantMedium(ref m) => m.material(),
+            SceneItem::HeterogeneousMedium(ref m) => m.material(),
+            SceneItem::Plane(ref p) => p.material(),
+            SceneItem::Disk(ref d) => d.material(),
+            SceneItem::Rect(ref r) => r.material(),
+            SceneItem::Cuboid(ref c) => c.material(),
+            SceneItem::Cylinder(ref c) => c.material(),
+            SceneItem::Cone(ref c) => c.material(),
+            SceneItem::Torus(ref t) => t.material(),
+            SceneItem::Csg(ref c) => c.material(),
+            SceneItem::Sdf(ref s) => s.material(),
+            SceneItem::Heightfield(ref h) => h.material(),
+            SceneItem::Curve(ref c) => c.material(),
+            SceneItem::Animated(_) => {
+                let inner = hit.inner.as_ref().expect("hits on animated groups come from their items");
+                inner.intersected.material(&inner.hit)
+            },
+            SceneItem::Triangle(ref t) => t.material(),
+            SceneItem::Quadric(ref q) => q.material(),
+            SceneItem::Implicit(ref i) => i.material(),
+            SceneItem::BilinearPatch(ref p) => p.material(),
+            SceneItem::BezierPatch(ref p) => p.material()
         }
     }
 
-    pub fn center(&self, time: f64) -> Vec3 {
+    // items without a finite bounding box
+    pub fn is_unbounded(&self) -> bool {
         match self {
-            SceneItem::Sphere(ref s) => s.center(),
-            SceneItem::MovingSphere(ref s) => s.center(time)
+            SceneItem::Plane(_) => true,
+            SceneItem::Quadric(ref q) => q.is_unbounded(),
+            SceneItem::Animated(ref a) => a.items().is_unbounded(),
+            _ => false
+        }
+    }
+
+    pub fn is_cut_out(&self, ray: &Ray, hit: &Hit) -> bool {
+        match self.material(hit).opacity {
+            Some(ref opacity) => {
+                let surface = self.surface(ray, hit);
+                opacity.value(&surface.coords, &surface.point) < ALPHA_CUTOFF
+            },
+            None => false
         }
     }
 }
@@ -107,57 +1178,197 @@ impl BoundingBox for SceneItem {
     fn bounding_box(&self) -> AABB {
         match self {
             SceneItem::Sphere(ref s) => s.bounding_box(),
-            SceneItem::MovingSphere(ref s) => s.bounding_box()
+            SceneItem::MovingSphere(ref s) => s.bounding_box(),
+            SceneItem::ConstantMedium(ref m) => m.bounding_box(),
+            SceneItem::HeterogeneousMedium(ref m) => m.bounding_box(),
+            SceneItem::Plane(ref p) => p.bounding_box(),
+            SceneItem::Disk(ref d) => d.bounding_box(),
+            SceneItem::Rect(ref r) => r.bounding_box(),
+            SceneItem::Cuboid(ref c) => c.bounding_box(),
+            SceneItem::Cylinder(ref c) => c.bounding_box(),
+            SceneItem::Cone(ref c) => c.bounding_box(),
+            SceneItem::Torus(ref t) => t.bounding_box(),
+            SceneItem::Csg(ref c) => c.bounding_box(),
+            SceneItem::Sdf(ref s) => s.bounding_box(),
+            SceneItem::Heightfield(ref h) => h.bounding_box(),
+            SceneItem::Curve(ref c) => c.bounding_box(),
+            SceneItem::Animated(ref a) => a.bounding_box(),
+            SceneItem::Triangle(ref t) => t.bounding_box(),
+            SceneItem::Quadric(ref q) => q.bounding_box(),
+            SceneItem::Implicit(ref i) => i.bounding_box(),
+            SceneItem::BilinearPatch(ref p) => p.bounding_box(),
+            SceneItem::BezierPatch(ref p) => p.bounding_box()
         }
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Clone, Debug)]
 pub enum Surface {
     Diffuse,
     Reflective { reflectivity: f32 },
-    Refractive { index: f32 }
+    // absorption scales how quickly light takes the material color inside, 0 for clear glass,
+    // dispersion replaces index in spectral rendering
+    Refractive { index: f32, absorption: f32, dispersion: Option<Dispersion> },
+    // scatters uniformly in all directions, for participating media
+    Isotropic,
+    // phase function of participating media, g in (-1, 1)
+    HenyeyGreenstein { g: f32 },
+    // light enters the object and random walks through its interior, the material color is the
+    // single scattering albedo, mean free path is per channel in scene units
+    Subsurface { mean_free_path: Color },
+    // stochastic blend of two materials, mask is the weight of `second`
+    Mix { first: Box<Material>, second: Box<Material>, mask: Coloration },
+    // clear dielectric coat over any base, the coating material's color tints the base
+    Coated { base: Box<Material>, index: f32, roughness: f32 },
+    // GGX conductor with roughness along the tangent and the bitangent, the material color is the
+    // normal reflectance, rotation turns the tangent by up to half a turn
+    AnisotropicMetal { roughness_u: f32, roughness_v: f32, rotation: Option<Coloration> },
+    // hair and fur, sigma_a is the absorption inside the fiber (see bsdf::melanin_absorption),
+    // beta_m and beta_n the longitudinal and azimuthal roughness and alpha the scale tilt in degrees
+    Fiber { sigma_a: Color, beta_m: f32, beta_n: f32, alpha: f32, index: f32 }
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Clone, Debug)]
 pub struct Material {
     pub color: Coloration,
     pub albedo: f32,
-    pub surface: Surface
+    pub surface: Surface,
+    // scales the fuzz of reflective surfaces
+    pub roughness: Option<Coloration>,
+    pub normal_mapping: Option<NormalMapping>,
+    // hits where opacity is below ALPHA_CUTOFF are skipped during traversal
+    pub opacity: Option<Coloration>,
+    // interference coating, changes the reflectance of reflective and refractive surfaces
+    pub thin_film: Option<ThinFilm>
 }
 
-#[derive(Copy, Clone, Debug)]
+pub const ALPHA_CUTOFF: f32 = 0.5;
+
+// perturbation of the shading normal
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone, Debug)]
+pub enum NormalMapping {
+    // height field from any scalar texture, displaced along the normal by `scale`
+    Bump { height: Coloration, scale: f64 },
+    // tangent-space normals, load the image with `ImageTexture::open_linear`
+    TangentSpace(Arc<ImageTexture>)
+}
+
+impl Material {
+    // cut-outs, normal mapping and film of the mix are those of `first`,
+    // each side still shades with its own once picked
+    pub fn mix(first: Material, second: Material, mask: Coloration) -> Material {
+        Material {
+            normal_mapping: first.normal_mapping.clone(),
+            opacity: first.opacity.clone(),
+            thin_film: first.thin_film,
+            surface: Surface::Mix { first: Box::new(first), second: Box::new(second), mask },
+            ..Material::default()
+        }
+    }
+
+    // cut-outs, normal mapping and film are those of the base
+    pub fn coated(base: Material, index: f32, roughness: f32) -> Material {
+        Material {
+            normal_mapping: base.normal_mapping.clone(),
+            opacity: base.opacity.clone(),
+            thin_film: base.thin_film,
+            surface: Surface::Coated { base: Box::new(base), index, roughness },
+            ..Material::default()
+        }
+    }
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Material {
+            color: Coloration::Color(Color::white()),
+            albedo: 1.,
+            surface: Surface::Diffuse,
+            roughness: None,
+            normal_mapping: None,
+            opacity: None,
+            thin_film: None
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
 pub enum Coloration {
     Color(Color),
     CheckerTexture(CheckerTexture),
-    NoiseTexture(NoiseTexture)
+    NoiseTexture(NoiseTexture),
+    ImageTexture(Arc<ImageTexture>),
+    TurbulenceTexture(TurbulenceTexture),
+    MarbleTexture(MarbleTexture),
+    WoodTexture(WoodTexture),
+    FbmTexture(FbmTexture),
+    CellularTexture(CellularTexture)
 }
 
 impl Coloration {
     pub fn color(&self, texture_coords: &TextureCoords, ray_point: &Vec3) -> Color {
-        match *self {
-            Coloration::Color(c) => c,
-            Coloration::CheckerTexture(t) => t.get_color(&texture_coords, &ray_point),
-            Coloration::NoiseTexture(t) => t.get_color(&texture_coords, &ray_point)
+        match self {
+            Coloration::Color(c) => *c,
+            Coloration::CheckerTexture(t) => t.get_color(texture_coords, ray_point),
+            Coloration::NoiseTexture(t) => t.get_color(texture_coords, ray_point),
+            Coloration::ImageTexture(t) => t.get_color(texture_coords, ray_point),
+            Coloration::TurbulenceTexture(t) => t.get_color(texture_coords, ray_point),
+            Coloration::MarbleTexture(t) => t.get_color(texture_coords, ray_point),
+            Coloration::WoodTexture(t) => t.get_color(texture_coords, ray_point),
+            Coloration::FbmTexture(t) => t.get_color(texture_coords, ray_point),
+            Coloration::CellularTexture(t) => t.get_color(texture_coords, ray_point)
         }
     }
+
+    // scalar channels (roughness etc.) read the luminance of the texture
+    pub fn value(&self, texture_coords: &TextureCoords, ray_point: &Vec3) -> f32 {
+        self.color(texture_coords, ray_point).luminance()
+    }
 }
 
+#[derive(Clone, Debug)]
 pub struct Scene {
     _items: Vec<SceneItem>,
-    bvh: BVH
+    bvh: Option<BVH>,
+    // unbounded items are tested one by one
+    unbounded: Vec<SceneItem>
 }
 
 impl Scene {
-    pub fn new(mut items: Vec<SceneItem>) -> Scene {
-        let scene_bvh = BVH::new(&mut items[..]);
+    pub fn new(items: Vec<SceneItem>) -> Scene {
+        let (unbounded, mut items): (Vec<SceneItem>, Vec<SceneItem>) = items.into_iter().partition(|item| item.is_unbounded());
+        let scene_bvh = if items.is_empty() { None } else { Some(BVH::new(&mut items[..])) };
         Scene {
             _items: items,
-            bvh: scene_bvh
+            bvh: scene_bvh,
+            unbounded
         }
     }
 
-    pub fn trace(&self, r: &Ray) -> Option<Intersection> {
-        self.bvh.intersect(&r)
+    // bounds of the items that have them
+    pub fn bounding_box(&self) -> Option<AABB> {
+        self.bvh.as_ref().map(|bvh| bvh.bbox)
+    }
+
+    pub fn is_unbounded(&self) -> bool {
+        !self.unbounded.is_empty()
+    }
+
+    // closest hit further than `t_min`
+    pub fn trace(&self, r: &Ray, t_min: f64) -> Option<Intersection<'_>> {
+        let mut closest = match self.bvh {
+            Some(ref bvh) => bvh.intersect(r, t_min),
+            None => None
+        };
+        for item in &self.unbounded {
+            if let Some(hit) = closest_hit(item, r, t_min) {
+                match closest {
+                    Some(ref c) if c.hit.dist <= hit.hit.dist => {},
+                    _ => closest = Some(hit)
+                }
+            }
+        }
+        closest
     }
 }
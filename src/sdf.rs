@@ -0,0 +1,77 @@
+use crate::vector::Vec3;
+
+// nodes of a signed distance function, negative inside
+#[derive(Clone, Debug)]
+pub enum SdfNode {
+    Sphere { center: Vec3, radius: f64 },
+    Cuboid { center: Vec3, half_size: Vec3 },
+    // ring in the xz plane
+    Torus { center: Vec3, major_radius: f64, minor_radius: f64 },
+    // blends the two surfaces over a distance of about `k`
+    SmoothUnion { a: Box<SdfNode>, b: Box<SdfNode>, k: f64 },
+    // infinite copies of `node` every `period` units, a zero period leaves that axis alone
+    Repeat { node: Box<SdfNode>, period: Vec3 },
+    // distance estimate of the power `power` Mandelbulb around `center`, about 1.2 * scale wide
+    Mandelbulb { center: Vec3, scale: f64, power: f64, iterations: u32 }
+}
+
+impl SdfNode {
+    pub fn smooth_union(a: SdfNode, b: SdfNode, k: f64) -> SdfNode {
+        SdfNode::SmoothUnion { a: Box::new(a), b: Box::new(b), k }
+    }
+
+    pub fn repeat(node: SdfNode, period: Vec3) -> SdfNode {
+        SdfNode::Repeat { node: Box::new(node), period }
+    }
+
+    pub fn distance(&self, p: &Vec3) -> f64 {
+        match self {
+            SdfNode::Sphere { center, radius } => (*p - *center).magn() - radius,
+            SdfNode::Cuboid { center, half_size } => {
+                let d = *p - *center;
+                let q = Vec3::new(d.x().abs() - half_size.x(), d.y().abs() - half_size.y(), d.z().abs() - half_size.z());
+                let outside = Vec3::new(q.x().max(0.), q.y().max(0.), q.z().max(0.)).magn();
+                outside + q.x().max(q.y()).max(q.z()).min(0.)
+            },
+            SdfNode::Torus { center, major_radius, minor_radius } => {
+                let d = *p - *center;
+                let ring = (d.x() * d.x() + d.z() * d.z()).sqrt() - major_radius;
+                (ring * ring + d.y() * d.y()).sqrt() - minor_radius
+            },
+            SdfNode::SmoothUnion { a, b, k } => {
+                let (da, db) = (a.distance(p), b.distance(p));
+                if *k <= 0. {
+                    return da.min(db);
+                }
+                let h = (0.5 + 0.5 * (db - da) / k).clamp(0., 1.);
+                db + (da - db) * h - k * h * (1. - h)
+            },
+            SdfNode::Repeat { node, period } => {
+                let wrap = |x: f64, period: f64| if period > 0. { x - period * (x / period).round() } else { x };
+                let q = Vec3::new(wrap(p.x(), period.x()), wrap(p.y(), period.y()), wrap(p.z(), period.z()));
+                node.distance(&q)
+            },
+            SdfNode::Mandelbulb { center, scale, power, iterations } => {
+                let c = (*p - *center) / *scale;
+                let mut z = c;
+                let mut dr = 1.;
+                let mut r = z.magn();
+                for _ in 0..*iterations {
+                    if r > 2. {
+                        break;
+                    }
+                    let theta = (z.z() / r.max(1e-12)).clamp(-1., 1.).acos() * power;
+                    let phi = z.y().atan2(z.x()) * power;
+                    dr = r.powf(power - 1.) * power * dr + 1.;
+                    let zr = r.powf(*power);
+                    z = zr * Vec3::new(theta.sin() * phi.cos(), theta.sin() * phi.sin(), theta.cos()) + c;
+                    r = z.magn();
+                }
+                if r < 1e-12 {
+                    return 0.;
+                }
+                0.5 * r.ln() * r / dr * scale
+            }
+        }
+    }
+}
@@ -0,0 +1,390 @@
+use crate::color::Color;
+use std::ops::{Add, Mul, Div};
+
+pub const LAMBDA_MIN: f32 = 380.;
+pub const LAMBDA_MAX: f32 = 720.;
+pub const CHANNELS: usize = 4;
+
+// wavelengths standing in for the r, g and b channels in rgb mode
+const RGB_LAMBDA: [f32; CHANNELS] = [610., 550., 465., 550.];
+
+// integral of the y matching function over [LAMBDA_MIN, LAMBDA_MAX]
+const CIE_Y_INTEGRAL: f32 = 106.911_87;
+
+// rgb of the equal energy spectrum, divided out so that white reflectance stays white
+const WHITE_RGB: [f32; 3] = [1.200_606_5, 0.949_637_5, 0.907_908_2];
+
+// maps rgb to weights of the red, green and blue basis spectra, computed from the basis below
+const RGB_TO_BASIS: [[f32; 3]; 3] = [
+    [1.062_281_2, -0.072_269_43, 0.009_988_25],
+    [-0.027_810_19, 1.018_154_5, 0.009_655_705],
+    [0.029_719_01, -0.004_013_935, 0.974_294_9]
+];
+
+// values per channel, either rgb or sampled wavelengths
+#[derive(Copy, Clone, Debug)]
+pub struct Spectrum {
+    values: [f32; CHANNELS]
+}
+
+impl Spectrum {
+    pub fn new(values: [f32; CHANNELS]) -> Spectrum {
+        Spectrum { values }
+    }
+
+    pub fn constant(value: f32) -> Spectrum {
+        Spectrum { values: [value; CHANNELS] }
+    }
+
+    pub fn zero() -> Spectrum {
+        Spectrum::constant(0.)
+    }
+
+    pub fn value(&self, channel: usize) -> f32 {
+        self.values[channel]
+    }
+
+    pub fn from_fn<F: Fn(usize) -> f32>(f: F) -> Spectrum {
+        let mut values = [0.; CHANNELS];
+        for (i, v) in values.iter_mut().enumerate() {
+            *v = f(i);
+        }
+        Spectrum { values }
+    }
+
+    pub fn map<F: Fn(f32) -> f32>(&self, f: F) -> Spectrum {
+        let mut values = self.values;
+        for v in values.iter_mut() {
+            *v = f(*v);
+        }
+        Spectrum { values }
+    }
+}
+
+impl Add for Spectrum {
+    type Output = Spectrum;
+
+    fn add(self, other: Spectrum) -> Spectrum {
+        let mut values = self.values;
+        for (v, o) in values.iter_mut().zip(other.values.iter()) {
+            *v += o;
+        }
+        Spectrum { values }
+    }
+}
+
+impl Mul for Spectrum {
+    type Output = Spectrum;
+
+    fn mul(self, other: Spectrum) -> Spectrum {
+        let mut values = self.values;
+        for (v, o) in values.iter_mut().zip(other.values.iter()) {
+            *v *= o;
+        }
+        Spectrum { values }
+    }
+}
+
+impl Mul<f32> for Spectrum {
+    type Output = Spectrum;
+
+    fn mul(self, other: f32) -> Spectrum {
+        self.map(|v| v * other)
+    }
+}
+
+impl Mul<Spectrum> for f32 {
+    type Output = Spectrum;
+
+    fn mul(self, spectrum: Spectrum) -> Spectrum {
+        spectrum * self
+    }
+}
+
+impl Div<f32> for Spectrum {
+    type Output = Spectrum;
+
+    fn div(self, other: f32) -> Spectrum {
+        self.map(|v| v / other)
+    }
+}
+
+// what the channels of a Spectrum stand for along one path
+#[derive(Copy, Clone, Debug)]
+pub enum Wavelengths {
+    Rgb,
+    // hero wavelength in channel 0 and three companions spread evenly over the visible range,
+    // companions are dropped once the path hits a dispersive surface
+    Spectral { lambda: [f32; CHANNELS], terminated: bool }
+}
+
+impl Wavelengths {
+    // `u` in [0, 1) picks the hero wavelength
+    pub fn sample(u: f64) -> Wavelengths {
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        let hero = LAMBDA_MIN + u as f32 * range;
+        let mut lambda = [hero; CHANNELS];
+        for (i, l) in lambda.iter_mut().enumerate().skip(1) {
+            *l = hero + i as f32 * range / CHANNELS as f32;
+            if *l >= LAMBDA_MAX {
+                *l -= range;
+            }
+        }
+        Wavelengths::Spectral { lambda, terminated: false }
+    }
+
+    pub fn is_spectral(&self) -> bool {
+        match self {
+            Wavelengths::Rgb => false,
+            Wavelengths::Spectral { .. } => true
+        }
+    }
+
+    pub fn lambda(&self, channel: usize) -> f32 {
+        match self {
+            Wavelengths::Rgb => RGB_LAMBDA[channel],
+            Wavelengths::Spectral { lambda, .. } => lambda[channel]
+        }
+    }
+
+    // number of leading channels that still carry light
+    pub fn channels(&self) -> usize {
+        match self {
+            Wavelengths::Rgb => 3,
+            Wavelengths::Spectral { terminated: true, .. } => 1,
+            Wavelengths::Spectral { terminated: false, .. } => CHANNELS
+        }
+    }
+
+    // mean over the channels that still carry light
+    pub fn average(&self, spectrum: &Spectrum) -> f32 {
+        let used = self.channels();
+        (0..used).map(|i| spectrum.value(i)).sum::<f32>() / used as f32
+    }
+
+    pub fn terminate_secondary(&mut self) {
+        if let Wavelengths::Spectral { ref mut terminated, .. } = self {
+            *terminated = true;
+        }
+    }
+
+    // reflectance-style upsampling, white maps to a constant 1
+    pub fn from_rgb(&self, color: Color) -> Spectrum {
+        match self {
+            Wavelengths::Rgb => Spectrum::new([color.r(), color.g(), color.b(), 0.]),
+            Wavelengths::Spectral { lambda, .. } => {
+                let rgb = [color.r(), color.g(), color.b()];
+                let mut weights = [0.; 3];
+                for (w, row) in weights.iter_mut().zip(RGB_TO_BASIS.iter()) {
+                    *w = row[0] * rgb[0] + row[1] * rgb[1] + row[2] * rgb[2];
+                }
+                let mut values = [0.; CHANNELS];
+                for (v, l) in values.iter_mut().zip(lambda.iter()) {
+                    let (r, g, b) = basis(*l);
+                    *v = (weights[0] * r + weights[1] * g + weights[2] * b).max(0.);
+                }
+                Spectrum::new(values)
+            }
+        }
+    }
+
+    pub fn to_rgb(&self, spectrum: Spectrum) -> Color {
+        match self {
+            Wavelengths::Rgb => Color::new(spectrum.value(0), spectrum.value(1), spectrum.value(2)),
+            Wavelengths::Spectral { lambda, terminated } => {
+                let used = if *terminated { 1 } else { CHANNELS };
+                let (mut x, mut y, mut z) = (0., 0., 0.);
+                for (i, l) in lambda.iter().enumerate().take(used) {
+                    let v = spectrum.value(i);
+                    x += cie_x(*l) * v;
+                    y += cie_y(*l) * v;
+                    z += cie_z(*l) * v;
+                }
+                // uniform wavelength pdf is 1 / range
+                let scale = (LAMBDA_MAX - LAMBDA_MIN) / (used as f32 * CIE_Y_INTEGRAL);
+                let (x, y, z) = (x * scale, y * scale, z * scale);
+
+                Color::new(
+                    (3.240_454 * x - 1.537_138 * y - 0.498_531 * z) / WHITE_RGB[0],
+                    (-0.969_266 * x + 1.876_011 * y + 0.041_556 * z) / WHITE_RGB[1],
+                    (0.055_643 * x - 0.204_026 * y + 1.057_225 * z) / WHITE_RGB[2]
+                )
+            }
+        }
+    }
+}
+
+// smooth partition of unity into red, green and blue spectra
+fn basis(lambda: f32) -> (f32, f32, f32) {
+    let b = 1. / (1. + ((lambda - 490.) / 10.).exp());
+    let r = 1. / (1. + (-(lambda - 590.) / 10.).exp());
+    (r, 1. - r - b, b)
+}
+
+fn gaussian(lambda: f32, mu: f32, sigma_low: f32, sigma_high: f32) -> f32 {
+    let sigma = if lambda < mu { sigma_low } else { sigma_high };
+    let t = (lambda - mu) / sigma;
+    (-0.5 * t * t).exp()
+}
+
+// CIE 1931 matching functions, multi-lobe fit by Wyman, Sloan and Shirley
+pub fn cie_x(lambda: f32) -> f32 {
+    1.056 * gaussian(lambda, 599.8, 37.9, 31.0) + 0.362 * gaussian(lambda, 442.0, 16.0, 26.7)
+        - 0.065 * gaussian(lambda, 501.1, 20.4, 26.2)
+}
+
+pub fn cie_y(lambda: f32) -> f32 {
+    0.821 * gaussian(lambda, 568.8, 46.9, 40.5) + 0.286 * gaussian(lambda, 530.9, 16.3, 31.1)
+}
+
+pub fn cie_z(lambda: f32) -> f32 {
+    1.217 * gaussian(lambda, 437.0, 11.8, 36.0) + 0.681 * gaussian(lambda, 459.0, 26.0, 13.8)
+}
+
+// wavelength dependent index of refraction, wavelengths in micrometers
+#[derive(Copy, Clone, Debug)]
+pub enum Dispersion {
+    // n = a + b / lambda^2
+    Cauchy { a: f32, b: f32 },
+    // n^2 = 1 + sum b_i lambda^2 / (lambda^2 - c_i)
+    Sellmeier { b: [f32; 3], c: [f32; 3] }
+}
+
+impl Dispersion {
+    pub fn ior(&self, lambda_nm: f32) -> f32 {
+        let l = lambda_nm / 1000.;
+        let l2 = l * l;
+        match self {
+            Dispersion::Cauchy { a, b } => a + b / l2,
+            Dispersion::Sellmeier { b, c } => {
+                let sum: f32 = b.iter().zip(c.iter()).map(|(b, c)| b * l2 / (l2 - c)).sum();
+                (1. + sum).sqrt()
+            }
+        }
+    }
+
+    // Schott N-BK7 crown glass
+    pub fn bk7() -> Dispersion {
+        Dispersion::Sellmeier {
+            b: [1.039_612, 0.231_792_3, 1.010_469_5],
+            c: [0.006_000_699, 0.020_017_914, 103.560_65]
+        }
+    }
+
+    pub fn diamond() -> Dispersion {
+        Dispersion::Sellmeier {
+            b: [0.3306, 4.3356, 0.],
+            c: [0.030_625, 0.011_236, 0.]
+        }
+    }
+}
+
+// amplitude reflection coefficients (s, p) at an interface, None on total internal reflection
+fn fresnel_amplitudes(n1: f32, n2: f32, cos1: f32) -> Option<(f32, f32, f32)> {
+    let sin2_sq = (n1 / n2) * (n1 / n2) * (1. - cos1 * cos1);
+    if sin2_sq >= 1. {
+        return None;
+    }
+    let cos2 = (1. - sin2_sq).sqrt();
+    let rs = (n1 * cos1 - n2 * cos2) / (n1 * cos1 + n2 * cos2);
+    let rp = (n2 * cos1 - n1 * cos2) / (n2 * cos1 + n1 * cos2);
+    Some((rs, rp, cos2))
+}
+
+// reflectance of two interfering reflections with phase difference `delta`
+fn airy(r12: f32, r23: f32, delta: f32) -> f32 {
+    let cross = 2. * r12 * r23 * delta.cos();
+    ((r12 * r12 + r23 * r23 + cross) / (1. + r12 * r12 * r23 * r23 + cross)).clamp(0., 1.)
+}
+
+// thin transparent layer (soap, oil, lens coating) on top of a surface
+#[derive(Copy, Clone, Debug)]
+pub struct ThinFilm {
+    // in nanometers
+    pub thickness: f32,
+    pub ior: f32
+}
+
+impl ThinFilm {
+    // light arriving from a medium with index `outer` onto the film over a dielectric with index `substrate`
+    pub fn dielectric_reflectance(&self, cos_i: f32, outer: f32, substrate: f32, lambda: f32) -> f32 {
+        let (r12s, r12p, cos2) = match fresnel_amplitudes(outer, self.ior, cos_i) {
+            Some(r) => r,
+            None => return 1.
+        };
+        let (r23s, r23p) = match fresnel_amplitudes(self.ior, substrate, cos2) {
+            Some((s, p, _)) => (s, p),
+            None => (1., 1.)
+        };
+        let delta = self.phase(cos2, lambda);
+        0.5 * (airy(r12s, r23s, delta) + airy(r12p, r23p, delta))
+    }
+
+    // film over a conductor, the metal is approximated by its normal reflectance with a phase flip
+    pub fn conductor_reflectance(&self, cos_i: f32, outer: f32, base: f32, lambda: f32) -> f32 {
+        let (r12s, r12p, cos2) = match fresnel_amplitudes(outer, self.ior, cos_i) {
+            Some(r) => r,
+            None => return 1.
+        };
+        let r23 = -base.clamp(0., 1.).sqrt();
+        let delta = self.phase(cos2, lambda);
+        0.5 * (airy(r12s, r23, delta) + airy(r12p, r23, delta))
+    }
+
+    fn phase(&self, cos_film: f32, lambda: f32) -> f32 {
+        4. * std::f32::consts::PI * self.ior * self.thickness * cos_film / lambda
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn white_round_trips_through_spectral_samples() {
+        // stratified hero wavelengths cover the visible range evenly
+        let samples = 400;
+        let mut acc = Color::black();
+        for i in 0..samples {
+            let wavelengths = Wavelengths::sample((i as f64 + 0.5) / samples as f64);
+            let spectrum = wavelengths.from_rgb(Color::white());
+            for channel in 0..CHANNELS {
+                assert!((spectrum.value(channel) - 1.).abs() < 1e-5);
+            }
+            acc = acc + wavelengths.to_rgb(spectrum);
+        }
+        let white = acc / samples as f32;
+        for &c in &[white.r(), white.g(), white.b()] {
+            assert!((c - 1.).abs() < 0.01, "white came back as {:?}", white);
+        }
+    }
+
+    #[test]
+    fn bk7_index_at_the_sodium_line() {
+        // catalogue value of N-BK7 at 587.6 nm is 1.5168, the d line of sodium at 589.3 nm is just below
+        assert!((Dispersion::bk7().ior(587.6) - 1.5168).abs() < 1e-4);
+        assert!((Dispersion::bk7().ior(589.) - 1.5167).abs() < 2e-4);
+        // normal dispersion: blue bends more than red
+        assert!(Dispersion::bk7().ior(450.) > Dispersion::bk7().ior(650.));
+    }
+
+    #[test]
+    fn vanishing_film_is_plain_fresnel() {
+        for &(outer, substrate) in &[(1., 1.5), (1.5, 1.), (1., 2.4)] {
+            for &cos_i in &[1., 0.8, 0.5, 0.95] {
+                let plain = match fresnel_amplitudes(outer, substrate, cos_i) {
+                    Some((rs, rp, _)) => 0.5 * (rs * rs + rp * rp),
+                    None => 1.
+                };
+                for &ior in &[1.33, 1.8] {
+                    let film = ThinFilm { thickness: 0., ior };
+                    let coated = film.dielectric_reflectance(cos_i, outer, substrate, 550.);
+                    assert!((coated - plain).abs() < 1e-5, "n {} -> {} at cos {}: {} vs {}", outer, substrate, cos_i, coated, plain);
+                }
+            }
+        }
+        // a conductor keeps its own reflectance at normal incidence under a vanishing film of index 1
+        let film = ThinFilm { thickness: 0., ior: 1. };
+        assert!((film.conductor_reflectance(1., 1., 0.7, 550.) - 0.7).abs() < 1e-5);
+    }
+}
@@ -1,26 +1,75 @@
 use crate::color::Color;
 use crate::vector::Vec3;
 use noise::{Perlin, NoiseFn};
+use image::{ImageResult, hdr::HDRDecoder};
+
+use std::f64::consts::PI;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CheckerMode {
+    // cubes in world space
+    Solid,
+    // squares in texture coords
+    Uv
+}
 
 #[derive(Copy, Clone, Debug)]
 pub struct CheckerTexture {
     odd: Color,
-    even: Color
+    even: Color,
+    // cells per unit
+    frequency: f64,
+    offset: Vec3,
+    mode: CheckerMode
 }
 
 
 impl CheckerTexture {
     pub fn new(c1: Color, c2: Color) -> CheckerTexture {
-        CheckerTexture {odd: c1, even: c2}
+        CheckerTexture::with_settings(c1, c2, 10. / PI, Vec3::zero(), CheckerMode::Solid)
     }
+
+    // in uv mode only x and y of the offset are used
+    pub fn with_settings(c1: Color, c2: Color, frequency: f64, offset: Vec3, mode: CheckerMode) -> CheckerTexture {
+        CheckerTexture { odd: c1, even: c2, frequency, offset, mode }
+    }
+
     pub fn get_color(&self, coords: &TextureCoords, ray_point: &Vec3) -> Color {
-        let sines = f64::sin(10. * ray_point.x()) * f64::sin(10. * ray_point.y()) * f64::sin(10. * ray_point.z());
-        if sines < 0. {
-            return self.odd
-        } else {
-            return self.even
-        }
+        let f = self.frequency;
+        // average of +1 (even) / -1 (odd) over the pixel footprint
+        let parity = match self.mode {
+            CheckerMode::Solid => {
+                let p = f * (*ray_point + self.offset);
+                let w = f * Vec3::new(
+                    coords.dpdx.x().abs() + coords.dpdy.x().abs(),
+                    coords.dpdx.y().abs() + coords.dpdy.y().abs(),
+                    coords.dpdx.z().abs() + coords.dpdy.z().abs()
+                );
+                checker_wave(p.x(), w.x()) * checker_wave(p.y(), w.y()) * checker_wave(p.z(), w.z())
+            },
+            CheckerMode::Uv => {
+                let wu = f * (coords.dudx.abs() + coords.dudy.abs());
+                let wv = f * (coords.dvdx.abs() + coords.dvdy.abs());
+                checker_wave(f * (coords.u + self.offset.x()), wu) * checker_wave(f * (coords.v + self.offset.y()), wv)
+            }
+        };
+
+        let t = (0.5 + 0.5 * parity) as f32;
+        t * self.even + (1. - t) * self.odd
+    }
+}
+
+// square wave (+1 on even cells, -1 on odd) box filtered over width `w`
+fn checker_wave(x: f64, w: f64) -> f64 {
+    if w < 1e-6 {
+        return if x.floor().rem_euclid(2.) < 1. { 1. } else { -1. };
     }
+    // integral of the square wave is a triangle wave
+    let integral = |y: f64| 1. - (y.rem_euclid(2.) - 1.).abs();
+    (integral(x + 0.5 * w) - integral(x - 0.5 * w)) / w
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -28,6 +77,12 @@ pub struct NoiseTexture {
     noise: Perlin
 }
 
+impl Default for NoiseTexture {
+    fn default() -> Self {
+        NoiseTexture::new()
+    }
+}
+
 impl NoiseTexture {
     pub fn new() -> NoiseTexture {
         NoiseTexture {
@@ -41,24 +96,634 @@ impl NoiseTexture {
     }
 }
 
-fn turbulance(point: &Vec3, depth: u8, noise_ref: &Perlin) -> f64 {
+// sum of octaves of signed noise, each `lacunarity` times finer and `gain` times weaker
+pub fn fbm(point: &Vec3, octaves: u8, lacunarity: f64, gain: f64, noise_ref: &Perlin) -> f64 {
     let mut acc = 0.;
     let mut weight: f64 = 1.;
     let mut point = point.as_arr();
 
-    for _ in 0..depth {
+    for _ in 0..octaves {
         acc += weight * noise_ref.get(point);
+        weight *= gain;
+        point[0] *= lacunarity;
+        point[1] *= lacunarity;
+        point[2] *= lacunarity;
+    }
+
+    acc
+}
+
+// sum of octaves of absolute noise, the signed sum is fbm with lacunarity 2 and gain 0.5
+fn turbulence(point: &Vec3, depth: u8, noise_ref: &Perlin) -> f64 {
+    let mut acc = 0.;
+    let mut weight: f64 = 1.;
+    let mut point = point.as_arr();
+
+    for _ in 0..depth {
+        acc += weight * noise_ref.get(point).abs();
         weight *= 0.5;
-        point[0] = point[0] * 2.;
-        point[1] = point[1] * 2.;
-        point[2] = point[2] * 2.;
+        point[0] *= 2.;
+        point[1] *= 2.;
+        point[2] *= 2.;
     }
 
     acc
 }
 
+fn ramp(low: Color, high: Color, t: f64) -> Color {
+    let t = t.clamp(0., 1.) as f32;
+    (1. - t) * low + t * high
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct TurbulenceTexture {
+    noise: Perlin,
+    scale: f64,
+    depth: u8
+}
+
+impl TurbulenceTexture {
+    pub fn new(scale: f64, depth: u8) -> TurbulenceTexture {
+        TurbulenceTexture { noise: Perlin::new(), scale, depth }
+    }
+
+    pub fn get_color(&self, _coords: &TextureCoords, ray_point: &Vec3) -> Color {
+        Color::gray(turbulence(&(self.scale * *ray_point), self.depth, &self.noise) as f32)
+    }
+}
+
+// veins along z: sine of the position warped by turbulence
+#[derive(Copy, Clone, Debug)]
+pub struct MarbleTexture {
+    noise: Perlin,
+    scale: f64,
+    depth: u8,
+    strength: f64,
+    base: Color,
+    vein: Color
+}
+
+impl MarbleTexture {
+    pub fn new(scale: f64, depth: u8, strength: f64, base: Color, vein: Color) -> MarbleTexture {
+        MarbleTexture { noise: Perlin::new(), scale, depth, strength, base, vein }
+    }
+
+    pub fn get_color(&self, _coords: &TextureCoords, ray_point: &Vec3) -> Color {
+        let p = self.scale * *ray_point;
+        let turb = turbulence(&p, self.depth, &self.noise);
+        let t = 0.5 * (1. + (p.z() + self.strength * turb).sin());
+        ramp(self.vein, self.base, t)
+    }
+}
+
+// concentric rings around the y axis, `scale` rings per unit, distorted by turbulence
+#[derive(Copy, Clone, Debug)]
+pub struct WoodTexture {
+    noise: Perlin,
+    scale: f64,
+    depth: u8,
+    strength: f64,
+    light: Color,
+    dark: Color
+}
+
+impl WoodTexture {
+    pub fn new(scale: f64, depth: u8, strength: f64, light: Color, dark: Color) -> WoodTexture {
+        WoodTexture { noise: Perlin::new(), scale, depth, strength, light, dark }
+    }
+
+    pub fn get_color(&self, _coords: &TextureCoords, ray_point: &Vec3) -> Color {
+        let p = self.scale * *ray_point;
+        let radius = (p.x() * p.x() + p.z() * p.z()).sqrt();
+        let turb = turbulence(&p, self.depth, &self.noise);
+        let ring = radius + self.strength * turb;
+        ramp(self.light, self.dark, ring - ring.floor())
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct FbmTexture {
+    noise: Perlin,
+    scale: f64,
+    octaves: u8,
+    lacunarity: f64,
+    gain: f64,
+    low: Color,
+    high: Color
+}
+
+impl FbmTexture {
+    pub fn new(scale: f64, octaves: u8, lacunarity: f64, gain: f64, low: Color, high: Color) -> FbmTexture {
+        FbmTexture { noise: Perlin::new(), scale, octaves, lacunarity, gain, low, high }
+    }
+
+    pub fn get_color(&self, _coords: &TextureCoords, ray_point: &Vec3) -> Color {
+        let value = fbm(&(self.scale * *ray_point), self.octaves, self.lacunarity, self.gain, &self.noise);
+        ramp(self.low, self.high, 0.5 * (value + 1.))
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DistanceMetric {
+    Euclidean,
+    Manhattan,
+    Chebyshev
+}
+
+impl DistanceMetric {
+    fn distance(&self, d: &Vec3) -> f64 {
+        match self {
+            DistanceMetric::Euclidean => d.magn(),
+            DistanceMetric::Manhattan => d.x().abs() + d.y().abs() + d.z().abs(),
+            DistanceMetric::Chebyshev => d.x().abs().max(d.y().abs()).max(d.z().abs())
+        }
+    }
+}
+
+// distance to the closest (F1) or second closest (F2) feature point
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CellularFeature {
+    F1,
+    F2,
+    F2MinusF1
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum CellularColoring {
+    // feature distance mapped between two colors
+    Gradient { low: Color, high: Color },
+    // flat random color per cell
+    Voronoi
+}
+
+fn hash_cell(seed: u32, x: i64, y: i64, z: i64) -> u32 {
+    let h = seed ^ (x as u32).wrapping_mul(0x8da6_b343)
+        ^ (y as u32).wrapping_mul(0xd816_3841)
+        ^ (z as u32).wrapping_mul(0xcb1a_b31f);
+    hash_u32(h)
+}
+
+fn hash_u32(mut h: u32) -> u32 {
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x85eb_ca6b);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0xc2b2_ae35);
+    h ^= h >> 16;
+    h
+}
+
+fn hash_unit(h: u32) -> f64 {
+    h as f64 / u32::MAX as f64
+}
+
+// Worley noise with one feature point per unit cell
+#[derive(Copy, Clone, Debug)]
+pub struct CellularTexture {
+    seed: u32,
+    scale: f64,
+    metric: DistanceMetric,
+    feature: CellularFeature,
+    coloring: CellularColoring
+}
+
+impl CellularTexture {
+    pub fn new(seed: u32, scale: f64, metric: DistanceMetric, feature: CellularFeature, coloring: CellularColoring) -> CellularTexture {
+        CellularTexture { seed, scale, metric, feature, coloring }
+    }
+
+    // returns F1, F2 and the hash of the closest cell
+    fn distances(&self, point: &Vec3) -> (f64, f64, u32) {
+        let cx = point.x().floor() as i64;
+        let cy = point.y().floor() as i64;
+        let cz = point.z().floor() as i64;
+        let mut f1 = f64::MAX;
+        let mut f2 = f64::MAX;
+        let mut closest = 0;
+
+        for x in cx - 1..=cx + 1 {
+            for y in cy - 1..=cy + 1 {
+                for z in cz - 1..=cz + 1 {
+                    let h = hash_cell(self.seed, x, y, z);
+                    let hy = hash_u32(h);
+                    let hz = hash_u32(hy);
+                    let feature_point = Vec3::new(
+                        x as f64 + hash_unit(h),
+                        y as f64 + hash_unit(hy),
+                        z as f64 + hash_unit(hz)
+                    );
+                    let dist = self.metric.distance(&(feature_point - *point));
+                    if dist < f1 {
+                        f2 = f1;
+                        f1 = dist;
+                        closest = h;
+                    } else if dist < f2 {
+                        f2 = dist;
+                    }
+                }
+            }
+        }
+
+        (f1, f2, closest)
+    }
+
+    pub fn value(&self, ray_point: &Vec3) -> f64 {
+        let (f1, f2, _) = self.distances(&(self.scale * *ray_point));
+        match self.feature {
+            CellularFeature::F1 => f1,
+            CellularFeature::F2 => f2,
+            CellularFeature::F2MinusF1 => f2 - f1
+        }
+    }
+
+    pub fn get_color(&self, _coords: &TextureCoords, ray_point: &Vec3) -> Color {
+        match self.coloring {
+            CellularColoring::Gradient { low, high } => ramp(low, high, self.value(ray_point)),
+            CellularColoring::Voronoi => {
+                let (_, _, cell) = self.distances(&(self.scale * *ray_point));
+                let g = hash_u32(cell ^ 0x68e3_1da4);
+                let b = hash_u32(g);
+                Color::new(hash_unit(cell) as f32, hash_unit(g) as f32, hash_unit(b) as f32)
+            }
+        }
+    }
+}
+
+// how texel lookups outside of [0, 1] are resolved
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TextureAddress {
+    Wrap,
+    Clamp,
+    Mirror
+}
+
+impl TextureAddress {
+    fn resolve(&self, ind: i64, size: i64) -> usize {
+        let resolved = match self {
+            TextureAddress::Wrap => ind.rem_euclid(size),
+            TextureAddress::Clamp => ind.max(0).min(size - 1),
+            TextureAddress::Mirror => {
+                let period = ind.rem_euclid(2 * size);
+                if period < size { period } else { 2 * size - 1 - period }
+            }
+        };
+        resolved as usize
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TextureFilter {
+    Nearest,
+    Bilinear,
+    // bilinear lookups blended between two mip levels chosen from the pixel footprint
+    Trilinear,
+    // up to `max_ratio` trilinear probes along the longer axis of the footprint
+    Anisotropic { max_ratio: u8 }
+}
+
+#[derive(Clone, Debug)]
+struct MipLevel {
+    width: usize,
+    height: usize,
+    // linear texels, row by row starting from the top of the image
+    texels: Vec<Color>
+}
+
+impl MipLevel {
+    fn downsample(&self) -> MipLevel {
+        let width = (self.width / 2).max(1);
+        let height = (self.height / 2).max(1);
+        let mut texels = Vec::with_capacity(width * height);
+
+        for y in 0..height {
+            for x in 0..width {
+                let x0 = (2 * x).min(self.width - 1);
+                let x1 = (2 * x + 1).min(self.width - 1);
+                let y0 = (2 * y).min(self.height - 1);
+                let y1 = (2 * y + 1).min(self.height - 1);
+                let sum = self.texels[y0 * self.width + x0] + self.texels[y0 * self.width + x1] +
+                    self.texels[y1 * self.width + x0] + self.texels[y1 * self.width + x1];
+                texels.push(sum / 4.);
+            }
+        }
+
+        MipLevel { width, height, texels }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ImageTexture {
+    // full resolution image followed by its halved copies down to 1x1
+    levels: Vec<MipLevel>,
+    address: TextureAddress,
+    filter: TextureFilter
+}
+
+impl ImageTexture {
+    pub fn new(width: usize, height: usize, texels: Vec<Color>, address: TextureAddress, filter: TextureFilter) -> ImageTexture {
+        assert_eq!(width * height, texels.len(), "texel count does not match image size");
+        let mut levels = vec![MipLevel { width, height, texels }];
+        while levels.last().map(|l| l.width > 1 || l.height > 1).unwrap_or(false) {
+            let next = levels.last().unwrap().downsample();
+            levels.push(next);
+        }
+
+        ImageTexture { levels, address, filter }
+    }
+
+    // loads PNG/JPEG (sRGB encoded) or Radiance HDR (already linear) image
+    pub fn open<P: AsRef<Path>>(path: P, address: TextureAddress, filter: TextureFilter) -> ImageResult<ImageTexture> {
+        ImageTexture::load(path.as_ref(), address, filter, true)
+    }
+
+    // loads image holding data rather than colors (normal maps etc.), texels are not decoded from sRGB
+    pub fn open_linear<P: AsRef<Path>>(path: P, address: TextureAddress, filter: TextureFilter) -> ImageResult<ImageTexture> {
+        ImageTexture::load(path.as_ref(), address, filter, false)
+    }
+
+    // loads the alpha channel as a gray texture, for opacity masks
+    pub fn open_alpha<P: AsRef<Path>>(path: P, address: TextureAddress, filter: TextureFilter) -> ImageResult<ImageTexture> {
+        let img = image::open(path)?.to_rgba();
+        let texels = img.pixels()
+            .map(|p| Color::gray(p[3] as f32 / 255.))
+            .collect();
+        Ok(ImageTexture::new(img.width() as usize, img.height() as usize, texels, address, filter))
+    }
+
+    fn load(path: &Path, address: TextureAddress, filter: TextureFilter, srgb: bool) -> ImageResult<ImageTexture> {
+        let is_hdr = path.extension()
+            .map(|ext| ext.eq_ignore_ascii_case("hdr"))
+            .unwrap_or(false);
+
+        if is_hdr {
+            let decoder = HDRDecoder::new(BufReader::new(File::open(path)?))?;
+            let meta = decoder.metadata();
+            let texels = decoder.read_image_hdr()?
+                .iter()
+                .map(|p| Color::new(p[0], p[1], p[2]))
+                .collect();
+            Ok(ImageTexture::new(meta.width as usize, meta.height as usize, texels, address, filter))
+        } else {
+            let img = image::open(path)?.to_rgb();
+            let texels = img.pixels()
+                .map(|p| if srgb {
+                    Color::from_srgb8(p[0], p[1], p[2])
+                } else {
+                    Color::new(p[0] as f32 / 255., p[1] as f32 / 255., p[2] as f32 / 255.)
+                })
+                .collect();
+            Ok(ImageTexture::new(img.width() as usize, img.height() as usize, texels, address, filter))
+        }
+    }
+
+    pub fn width(&self) -> usize { self.levels[0].width }
+
+    pub fn height(&self) -> usize { self.levels[0].height }
+
+    pub fn mip_levels(&self) -> usize { self.levels.len() }
+
+    fn texel(&self, level: usize, x: i64, y: i64) -> Color {
+        let mip = &self.levels[level];
+        let x = self.address.resolve(x, mip.width as i64);
+        let y = self.address.resolve(y, mip.height as i64);
+        mip.texels[y * mip.width + x]
+    }
+
+    fn nearest(&self, level: usize, u: f64, v: f64) -> Color {
+        let mip = &self.levels[level];
+        let x = u * mip.width as f64;
+        let y = (1. - v) * mip.height as f64;
+        self.texel(level, x.floor() as i64, y.floor() as i64)
+    }
+
+    fn bilinear(&self, level: usize, u: f64, v: f64) -> Color {
+        let mip = &self.levels[level];
+        let x = u * mip.width as f64 - 0.5;
+        let y = (1. - v) * mip.height as f64 - 0.5;
+        let x0 = x.floor();
+        let y0 = y.floor();
+        let fx = (x - x0) as f32;
+        let fy = (y - y0) as f32;
+        let x0 = x0 as i64;
+        let y0 = y0 as i64;
+
+        (1. - fy) * ((1. - fx) * self.texel(level, x0, y0) + fx * self.texel(level, x0 + 1, y0)) +
+            fy * ((1. - fx) * self.texel(level, x0, y0 + 1) + fx * self.texel(level, x0 + 1, y0 + 1))
+    }
+
+    // `width` is the footprint size in texels of the full resolution image
+    fn trilinear(&self, u: f64, v: f64, width: f64) -> Color {
+        let max_level = (self.levels.len() - 1) as f64;
+        let lod = width.max(1e-8).log2().max(0.).min(max_level);
+        let lower = lod.floor() as usize;
+        if lower as f64 >= max_level {
+            return self.bilinear(lower, u, v);
+        }
+        let t = (lod - lower as f64) as f32;
+        (1. - t) * self.bilinear(lower, u, v) + t * self.bilinear(lower + 1, u, v)
+    }
+
+    // v = 0 is the bottom row of the image
+    pub fn sample(&self, u: f64, v: f64) -> Color {
+        match self.filter {
+            TextureFilter::Nearest => self.nearest(0, u, v),
+            _ => self.bilinear(0, u, v)
+        }
+    }
+
+    // filtered lookup over the pixel footprint described by the coords derivatives
+    pub fn sample_footprint(&self, coords: &TextureCoords) -> Color {
+        let w = self.width() as f64;
+        let h = self.height() as f64;
+        // footprint axes in texels
+        let (ax, ay) = (coords.dudx * w, coords.dvdx * h);
+        let (bx, by) = (coords.dudy * w, coords.dvdy * h);
+        let len_a = (ax * ax + ay * ay).sqrt();
+        let len_b = (bx * bx + by * by).sqrt();
+
+        match self.filter {
+            TextureFilter::Nearest | TextureFilter::Bilinear => self.sample(coords.u, coords.v),
+            TextureFilter::Trilinear => self.trilinear(coords.u, coords.v, len_a.max(len_b)),
+            TextureFilter::Anisotropic { max_ratio } => {
+                let (major, minor, du, dv) = if len_a > len_b {
+                    (len_a, len_b, coords.dudx, coords.dvdx)
+                } else {
+                    (len_b, len_a, coords.dudy, coords.dvdy)
+                };
+                let ratio = (major / minor.max(1e-8)).min(max_ratio.max(1) as f64);
+                let probes = ratio.ceil().max(1.) as usize;
+                let width = major / ratio;
+
+                let mut acc = Color::black();
+                for i in 0..probes {
+                    let t = (i as f64 + 0.5) / probes as f64 - 0.5;
+                    acc = acc + self.trilinear(coords.u + t * du, coords.v + t * dv, width);
+                }
+                acc / probes as f32
+            }
+        }
+    }
+
+    pub fn get_color(&self, coords: &TextureCoords, _ray_point: &Vec3) -> Color {
+        self.sample_footprint(coords)
+    }
+}
+
+// derivatives are zero unless the ray carried differentials
+#[derive(Copy, Clone, Debug)]
 pub struct TextureCoords {
     pub u: f64,
-    pub v: f64
+    pub v: f64,
+    pub dudx: f64,
+    pub dvdx: f64,
+    pub dudy: f64,
+    pub dvdy: f64,
+    // footprint of the pixel on the surface, used by solid textures
+    pub dpdx: Vec3,
+    pub dpdy: Vec3
+}
+
+impl TextureCoords {
+    pub fn new(u: f64, v: f64) -> TextureCoords {
+        TextureCoords {
+            u, v,
+            dudx: 0., dvdx: 0., dudy: 0., dvdy: 0.,
+            dpdx: Vec3::zero(),
+            dpdy: Vec3::zero()
+        }
+    }
+
+    // latitude-longitude mapping of a unit direction, v grows from -y to +y
+    pub fn spherical(direction: &Vec3) -> TextureCoords {
+        let theta = (-direction.y()).clamp(-1., 1.).acos();
+        let phi = (-direction.z()).atan2(direction.x()) + PI;
+        TextureCoords::new(phi / (2. * PI), theta / PI)
+    }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn address_modes_resolve_out_of_range_indices() {
+        let wrapped: Vec<usize> = (-4..8).map(|i| TextureAddress::Wrap.resolve(i, 4)).collect();
+        assert_eq!(wrapped, vec![0, 1, 2, 3, 0, 1, 2, 3, 0, 1, 2, 3]);
+        let clamped: Vec<usize> = (-4..8).map(|i| TextureAddress::Clamp.resolve(i, 4)).collect();
+        assert_eq!(clamped, vec![0, 0, 0, 0, 0, 1, 2, 3, 3, 3, 3, 3]);
+        let mirrored: Vec<usize> = (-4..8).map(|i| TextureAddress::Mirror.resolve(i, 4)).collect();
+        assert_eq!(mirrored, vec![3, 2, 1, 0, 0, 1, 2, 3, 3, 2, 1, 0]);
+    }
+
+    #[test]
+    fn bilinear_sample_blends_neighbouring_texels() {
+        let texels = vec![Color::black(), Color::white(), Color::white(), Color::black()];
+        let texture = ImageTexture::new(2, 2, texels, TextureAddress::Clamp, TextureFilter::Bilinear);
+        // texel centres return the texel itself, the image centre the average of all four
+        assert!((texture.sample(0.25, 0.75).r() - 0.).abs() < 1e-6);
+        assert!((texture.sample(0.75, 0.75).r() - 1.).abs() < 1e-6);
+        assert!((texture.sample(0.5, 0.5).r() - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn trilinear_level_follows_the_footprint() {
+        // 4x4 black and white checker, every texel differs from its neighbours so
+        // levels 1 and 2 average to plain gray
+        let texels = (0..16).map(|i| if (i % 4 + i / 4) % 2 == 0 { Color::black() } else { Color::white() }).collect();
+        let texture = ImageTexture::new(4, 4, texels, TextureAddress::Wrap, TextureFilter::Trilinear);
+        assert_eq!(texture.mip_levels(), 3);
+
+        let lookup = |footprint: f64| {
+            let mut coords = TextureCoords::new(0.125, 0.875);
+            coords.dudx = footprint / 4.;
+            coords.dvdy = footprint / 4.;
+            texture.sample_footprint(&coords).r()
+        };
+        // a footprint of one texel stays on the full resolution image
+        assert!(lookup(1.) < 1e-6);
+        // two texels select level 1, halfway in log2 blends levels 0 and 1
+        assert!((lookup(2.) - 0.5).abs() < 1e-6);
+        assert!((lookup(2f64.sqrt()) - 0.25).abs() < 1e-6);
+        // footprints beyond the smallest level clamp to it
+        assert!((lookup(64.) - 0.5).abs() < 1e-6);
+    }
+
+    fn sample_points() -> Vec<Vec3> {
+        (0..500).map(|i| {
+            let i = i as f64;
+            Vec3::new((i * 0.731).sin() * 7.3, (i * 1.137).cos() * 5.1, i * 0.0913 - 20.)
+        }).collect()
+    }
+
+    #[test]
+    fn fractal_sums_stay_within_their_octave_bounds() {
+        let noise = Perlin::new();
+        for p in sample_points() {
+            let turb = turbulence(&p, 5, &noise);
+            assert!((0. ..1.9375 + 1e-9).contains(&turb), "turbulence {} at {:?}", turb, p);
+            let sum = fbm(&p, 4, 2., 0.5, &noise);
+            assert!(sum.abs() <= 1.875 + 1e-9, "fbm {} at {:?}", sum, p);
+            // the signed octaves of turbulence are plain fbm
+            let folded: f64 = (0..5).map(|i| 0.5f64.powi(i) * noise.get((2f64.powi(i) * p).as_arr()).abs()).sum();
+            assert!((folded - turb).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn marble_and_wood_stay_between_their_colors() {
+        let marble = MarbleTexture::new(4., 6, 5., Color::white(), Color::new(0.2, 0.1, 0.));
+        let wood = WoodTexture::new(3., 4, 0.8, Color::new(0.9, 0.6, 0.3), Color::new(0.4, 0.2, 0.1));
+        let coords = TextureCoords::new(0., 0.);
+        for p in sample_points() {
+            let c = marble.get_color(&coords, &p);
+            assert!((0.2..=1.).contains(&c.r()) && (0.1..=1.).contains(&c.g()) && (0. ..=1.).contains(&c.b()));
+            let c = wood.get_color(&coords, &p);
+            assert!((0.4..=0.9).contains(&c.r()) && (0.2..=0.6).contains(&c.g()) && (0.1..=0.3).contains(&c.b()));
+        }
+    }
+
+    #[test]
+    fn cellular_f1_never_exceeds_f2() {
+        for &metric in &[DistanceMetric::Euclidean, DistanceMetric::Manhattan, DistanceMetric::Chebyshev] {
+            let gray = CellularColoring::Gradient { low: Color::black(), high: Color::white() };
+            let texture = CellularTexture::new(7, 1.3, metric, CellularFeature::F2MinusF1, gray);
+            for p in sample_points() {
+                let (f1, f2, _) = texture.distances(&p);
+                assert!(f1 >= 0. && f1 <= f2, "{:?}: F1 {} F2 {} at {:?}", metric, f1, f2, p);
+                assert!(texture.value(&(p / 1.3)) >= -1e-12);
+            }
+        }
+    }
+
+    #[test]
+    fn checker_modes_pick_cells_by_position_or_uv() {
+        let solid = CheckerTexture::with_settings(Color::black(), Color::white(), 1., Vec3::zero(), CheckerMode::Solid);
+        let uv = CheckerTexture::with_settings(Color::black(), Color::white(), 4., Vec3::zero(), CheckerMode::Uv);
+        let origin = TextureCoords::new(0., 0.);
+        // cell (0, 0, 0) is even, a step along any axis flips the parity
+        assert_eq!(solid.get_color(&origin, &Vec3::new(0.5, 0.5, 0.5)).r(), 1.);
+        assert_eq!(solid.get_color(&origin, &Vec3::new(1.5, 0.5, 0.5)).r(), 0.);
+        assert_eq!(solid.get_color(&origin, &Vec3::new(1.5, -0.5, 0.5)).r(), 1.);
+        // uv mode ignores the point
+        assert_eq!(uv.get_color(&TextureCoords::new(0.1, 0.1), &Vec3::new(1.5, 0.5, 0.5)).r(), 1.);
+        assert_eq!(uv.get_color(&TextureCoords::new(0.3, 0.1), &Vec3::new(1.5, 0.5, 0.5)).r(), 0.);
+    }
+
+    #[test]
+    fn checker_box_filter_averages_to_gray() {
+        // a footprint spanning whole numbers of cells sees as much odd as even
+        for &x in &[0.1, 0.5, 3.7] {
+            assert!(checker_wave(x, 2.).abs() < 1e-12);
+            assert!(checker_wave(x, 4.).abs() < 1e-12);
+        }
+        // a narrow footprint inside a cell keeps its parity
+        assert!((checker_wave(0.5, 0.5) - 1.).abs() < 1e-12);
+        assert!((checker_wave(1.5, 0.5) + 1.).abs() < 1e-12);
+
+        let uv = CheckerTexture::with_settings(Color::black(), Color::white(), 4., Vec3::zero(), CheckerMode::Uv);
+        let mut coords = TextureCoords::new(0.1, 0.1);
+        coords.dudx = 0.5;
+        coords.dvdy = 0.5;
+        assert!((uv.get_color(&coords, &Vec3::zero()).r() - 0.5).abs() < 1e-6);
+    }
+}
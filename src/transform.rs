@@ -0,0 +1,240 @@
+use crate::vector::Vec3;
+use crate::aabb::{AABB, surrounding_box};
+
+use std::cmp::Ordering;
+
+// unit quaternion for rotations
+#[derive(Copy, Clone, Debug)]
+pub struct Quat {
+    w: f64,
+    v: Vec3
+}
+
+impl Quat {
+    pub fn identity() -> Quat {
+        Quat { w: 1., v: Vec3::zero() }
+    }
+
+    // rotation by `angle` degrees around `axis`
+    pub fn from_axis_angle(axis: Vec3, angle: f64) -> Quat {
+        let half = 0.5 * angle.to_radians();
+        Quat { w: half.cos(), v: half.sin() * axis.normalize() }
+    }
+
+    fn dot(&self, other: &Quat) -> f64 {
+        self.w * other.w + self.v.dot(&other.v)
+    }
+
+    fn normalize(&self) -> Quat {
+        let length = self.dot(self).sqrt();
+        Quat { w: self.w / length, v: self.v / length }
+    }
+
+    // angle in radians of the shortest rotation between the two
+    fn angle_to(&self, other: &Quat) -> f64 {
+        2. * self.dot(other).abs().min(1.).acos()
+    }
+
+    fn conjugate(&self) -> Quat {
+        Quat { w: self.w, v: -self.v }
+    }
+
+    pub fn rotate(&self, p: Vec3) -> Vec3 {
+        let t = 2. * self.v.cross(&p);
+        p + self.w * t + self.v.cross(&t)
+    }
+
+    // shortest arc between two rotations at constant angular speed
+    pub fn slerp(&self, other: &Quat, t: f64) -> Quat {
+        let mut other = *other;
+        let mut cos = self.dot(&other);
+        if cos < 0. {
+            other = Quat { w: -other.w, v: -other.v };
+            cos = -cos;
+        }
+        if cos > 0.9995 {
+            // nearly parallel, lerp avoids dividing by a tiny sine
+            return Quat { w: self.w + t * (other.w - self.w), v: self.v + t * (other.v - self.v) }.normalize();
+        }
+        let theta = cos.acos();
+        let a = ((1. - t) * theta).sin() / theta.sin();
+        let b = (t * theta).sin() / theta.sin();
+        Quat { w: a * self.w + b * other.w, v: a * self.v + b * other.v }.normalize()
+    }
+}
+
+// scale, then rotate, then translate
+#[derive(Copy, Clone, Debug)]
+pub struct Transform {
+    translation: Vec3,
+    rotation: Quat,
+    scale: Vec3
+}
+
+impl Transform {
+    pub fn new(translation: Vec3, rotation: Quat, scale: Vec3) -> Transform {
+        Transform { translation, rotation: rotation.normalize(), scale }
+    }
+
+    pub fn identity() -> Transform {
+        Transform::new(Vec3::zero(), Quat::identity(), Vec3::new(1., 1., 1.))
+    }
+
+    pub fn translation(translation: Vec3) -> Transform {
+        Transform::new(translation, Quat::identity(), Vec3::new(1., 1., 1.))
+    }
+
+    pub fn point_to_world(&self, p: Vec3) -> Vec3 {
+        self.translation + self.vector_to_world(p)
+    }
+
+    pub fn vector_to_world(&self, v: Vec3) -> Vec3 {
+        self.rotation.rotate(v * self.scale)
+    }
+
+    // normals take the inverse transpose, which undoes the scale instead
+    pub fn normal_to_world(&self, n: Vec3) -> Vec3 {
+        self.covector_to_world(n).normalize()
+    }
+
+    // inverse transpose without renormalizing, for normals and their derivatives
+    pub fn covector_to_world(&self, n: Vec3) -> Vec3 {
+        self.rotation.rotate(n / self.scale)
+    }
+
+    pub fn point_to_local(&self, p: Vec3) -> Vec3 {
+        self.vector_to_local(p - self.translation)
+    }
+
+    pub fn vector_to_local(&self, v: Vec3) -> Vec3 {
+        self.rotation.conjugate().rotate(v) / self.scale
+    }
+
+    pub fn bounds_to_world(&self, bbox: &AABB) -> AABB {
+        let corners = corners(bbox);
+        let first = self.point_to_world(corners[0]);
+        corners.iter().skip(1).fold(AABB::new(first, first), |bounds, corner| {
+            let p = self.point_to_world(*corner);
+            surrounding_box(&bounds, &AABB::new(p, p))
+        })
+    }
+
+    // translation and scale move linearly, rotation at constant angular speed
+    fn blend(&self, other: &Transform, t: f64) -> Transform {
+        Transform {
+            translation: self.translation + t * (other.translation - self.translation),
+            rotation: self.rotation.slerp(&other.rotation, t),
+            scale: self.scale + t * (other.scale - self.scale)
+        }
+    }
+}
+
+fn corners(bbox: &AABB) -> [Vec3; 8] {
+    let (min, max) = (bbox.min(), bbox.max());
+    let mut corners = [Vec3::zero(); 8];
+    for (i, corner) in corners.iter_mut().enumerate() {
+        *corner = Vec3::new(
+            if i & 1 == 0 { min.x() } else { max.x() },
+            if i & 2 == 0 { min.y() } else { max.y() },
+            if i & 4 == 0 { min.z() } else { max.z() }
+        );
+    }
+    corners
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Keyframe {
+    pub time: f64,
+    pub transform: Transform
+}
+
+// motion between keys is sampled this often when bounding it
+const BOUND_STEPS: usize = 16;
+
+// transform varying over the shutter, held constant before the first and after the last key
+#[derive(Clone, Debug)]
+pub struct AnimatedTransform {
+    keys: Vec<Keyframe>
+}
+
+impl AnimatedTransform {
+    pub fn new(mut keys: Vec<Keyframe>) -> AnimatedTransform {
+        if keys.is_empty() {
+            keys.push(Keyframe { time: 0., transform: Transform::identity() });
+        }
+        keys.sort_by(|a, b| a.time.partial_cmp(&b.time).unwrap_or(Ordering::Equal));
+        AnimatedTransform { keys }
+    }
+
+    pub fn at(&self, time: f64) -> Transform {
+        let first = &self.keys[0];
+        let last = &self.keys[self.keys.len() - 1];
+        if time <= first.time {
+            return first.transform;
+        }
+        if time >= last.time {
+            return last.transform;
+        }
+        let next = self.keys.iter().position(|k| k.time > time).unwrap();
+        let (a, b) = (&self.keys[next - 1], &self.keys[next]);
+        a.transform.blend(&b.transform, (time - a.time) / (b.time - a.time))
+    }
+
+    // union of the bounds at samples between the keys, padded by how far a corner can get from
+    // the nearest sample: over a pair of keys it moves at most the translation, plus the rotation
+    // angle times its largest distance from the pivot, plus the change of scale
+    pub fn motion_bounds(&self, bbox: &AABB) -> AABB {
+        let mut bounds = self.keys[0].transform.bounds_to_world(bbox);
+        for pair in self.keys.windows(2) {
+            let (a, b) = (&pair[0].transform, &pair[1].transform);
+            let angle = a.rotation.angle_to(&b.rotation);
+            let travel = corners(bbox).iter().fold(0., |travel: f64, c| {
+                let reach = (*c * a.scale).magn().max((*c * b.scale).magn());
+                travel.max((b.translation - a.translation).magn() + angle * reach + (*c * (b.scale - a.scale)).magn())
+            });
+            let pad = 0.5 * travel / BOUND_STEPS as f64;
+            let pad = Vec3::new(pad, pad, pad);
+            for i in 0..=BOUND_STEPS {
+                let sample = a.blend(b, i as f64 / BOUND_STEPS as f64).bounds_to_world(bbox);
+                bounds = surrounding_box(&bounds, &AABB::new(sample.min() - pad, sample.max() + pad));
+            }
+        }
+        bounds
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contains(bounds: &AABB, p: &Vec3) -> bool {
+        (0..3).all(|i| bounds.min().get_ind(i) <= p.get_ind(i) && p.get_ind(i) <= bounds.max().get_ind(i))
+    }
+
+    #[test]
+    fn motion_bounds_hold_every_corner() {
+        let bbox = AABB::new(Vec3::new(1., -0.5, -0.5), Vec3::new(3., 0.5, 0.5));
+        let spin = AnimatedTransform::new(vec![
+            Keyframe { time: 0., transform: Transform::identity() },
+            Keyframe { time: 1., transform: Transform::new(Vec3::new(0., 1., 0.), Quat::from_axis_angle(Vec3::new(0., 1., 0.), 170.), Vec3::new(1.5, 1., 1.)) }
+        ]);
+        let bounds = spin.motion_bounds(&bbox);
+        for step in 0..=1000 {
+            let transform = spin.at(step as f64 / 1000.);
+            for corner in &corners(&bbox) {
+                let p = transform.point_to_world(*corner);
+                assert!(contains(&bounds, &p), "{:?} outside {:?}", p, bounds);
+            }
+        }
+    }
+
+    #[test]
+    fn keys_with_nan_times_do_not_panic() {
+        let keys = vec![
+            Keyframe { time: 1., transform: Transform::translation(Vec3::new(1., 0., 0.)) },
+            Keyframe { time: f64::NAN, transform: Transform::identity() },
+            Keyframe { time: 0., transform: Transform::identity() }
+        ];
+        AnimatedTransform::new(keys).at(0.5);
+    }
+}
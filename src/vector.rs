@@ -141,6 +141,18 @@ impl Div<f64> for Vec3 {
     }
 }
 
+impl Div for Vec3 {
+    type Output = Vec3;
+
+    fn div(self, other: Vec3) -> Vec3 {
+        Vec3 {
+            x: self.x / other.x,
+            y: self.y / other.y,
+            z: self.z / other.z
+        }
+    }
+}
+
 impl Neg for Vec3 {
     type Output = Vec3;
 
@@ -152,3 +164,42 @@ impl Neg for Vec3 {
         }
     }
 }
+
+// orthonormal basis with y along an axis, for primitives modelled around the y axis
+#[derive(Copy, Clone, Debug)]
+pub struct Frame {
+    origin: Vec3,
+    x: Vec3,
+    y: Vec3,
+    z: Vec3
+}
+
+impl Frame {
+    pub fn new(origin: Vec3, axis: Vec3) -> Frame {
+        let y = axis.normalize();
+        let helper = if y.x.abs() > 0.9 { Vec3::new(0., 0., 1.) } else { Vec3::new(1., 0., 0.) };
+        let x = (helper - helper.dot(&y) * y).normalize();
+        let z = x.cross(&y);
+        Frame { origin, x, y, z }
+    }
+
+    pub fn origin(&self) -> Vec3 { self.origin }
+
+    pub fn axis(&self) -> Vec3 { self.y }
+
+    pub fn point_to_local(&self, p: Vec3) -> Vec3 {
+        self.vector_to_local(p - self.origin)
+    }
+
+    pub fn vector_to_local(&self, v: Vec3) -> Vec3 {
+        Vec3::new(v.dot(&self.x), v.dot(&self.y), v.dot(&self.z))
+    }
+
+    pub fn point_to_world(&self, p: Vec3) -> Vec3 {
+        self.origin + self.vector_to_world(p)
+    }
+
+    pub fn vector_to_world(&self, v: Vec3) -> Vec3 {
+        v.x * self.x + v.y * self.y + v.z * self.z
+    }
+}
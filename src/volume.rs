@@ -0,0 +1,111 @@
+use crate::vector::Vec3;
+use crate::aabb::AABB;
+use crate::scene::Coloration;
+use crate::texture::TextureCoords;
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+// spatially varying density in [0, 1], scaled by the medium's max density
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone, Debug)]
+pub enum DensityField {
+    // scalar value of any texture (e.g. NoiseTexture) evaluated at the point
+    Texture(Coloration),
+    Grid(Arc<VoxelGrid>)
+}
+
+impl DensityField {
+    pub fn density(&self, point: &Vec3) -> f64 {
+        let value = match self {
+            DensityField::Texture(t) => t.value(&TextureCoords::new(0., 0.), point) as f64,
+            DensityField::Grid(g) => g.sample(point)
+        };
+        value.clamp(0., 1.)
+    }
+}
+
+// dense grid of density values stretched over `bounds`, x varies fastest
+#[derive(Clone, Debug)]
+pub struct VoxelGrid {
+    nx: usize,
+    ny: usize,
+    nz: usize,
+    values: Vec<f32>,
+    bounds: AABB
+}
+
+impl VoxelGrid {
+    pub fn new(nx: usize, ny: usize, nz: usize, values: Vec<f32>, bounds: AABB) -> VoxelGrid {
+        assert_eq!(nx * ny * nz, values.len(), "value count does not match grid size");
+        VoxelGrid { nx, ny, nz, values, bounds }
+    }
+
+    // raw little-endian f32 values
+    pub fn open_raw<P: AsRef<Path>>(path: P, nx: usize, ny: usize, nz: usize, bounds: AABB) -> io::Result<VoxelGrid> {
+        let bytes = fs::read(path)?;
+        if bytes.len() != nx * ny * nz * 4 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "file size does not match grid size"));
+        }
+        let values = bytes.chunks(4)
+            .map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+            .collect();
+        Ok(VoxelGrid::new(nx, ny, nz, values, bounds))
+    }
+
+    pub fn bounds(&self) -> AABB { self.bounds }
+
+    fn value(&self, x: i64, y: i64, z: i64) -> f64 {
+        if x < 0 || y < 0 || z < 0 || x >= self.nx as i64 || y >= self.ny as i64 || z >= self.nz as i64 {
+            return 0.;
+        }
+        let (x, y, z) = (x as usize, y as usize, z as usize);
+        self.values[(z * self.ny + y) * self.nx + x] as f64
+    }
+
+    // trilinear interpolation between voxel centers, zero outside of the grid
+    pub fn sample(&self, point: &Vec3) -> f64 {
+        let size = self.bounds.max() - self.bounds.min();
+        let local = *point - self.bounds.min();
+        let gx = local.x() / size.x() * self.nx as f64 - 0.5;
+        let gy = local.y() / size.y() * self.ny as f64 - 0.5;
+        let gz = local.z() / size.z() * self.nz as f64 - 0.5;
+        let (x0, y0, z0) = (gx.floor(), gy.floor(), gz.floor());
+        let (fx, fy, fz) = (gx - x0, gy - y0, gz - z0);
+        let (x0, y0, z0) = (x0 as i64, y0 as i64, z0 as i64);
+
+        let lerp = |a: f64, b: f64, t: f64| a + (b - a) * t;
+        let c00 = lerp(self.value(x0, y0, z0), self.value(x0 + 1, y0, z0), fx);
+        let c10 = lerp(self.value(x0, y0 + 1, z0), self.value(x0 + 1, y0 + 1, z0), fx);
+        let c01 = lerp(self.value(x0, y0, z0 + 1), self.value(x0 + 1, y0, z0 + 1), fx);
+        let c11 = lerp(self.value(x0, y0 + 1, z0 + 1), self.value(x0 + 1, y0 + 1, z0 + 1), fx);
+        lerp(lerp(c00, c10, fy), lerp(c01, c11, fy), fz)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn voxel_grid_sample_is_trilinear() {
+        // 3x2x2 voxels over [0, 3] x [0, 2] x [0, 2] holding x + 2y + 4z of the voxel center
+        let values = (0..12).map(|i| {
+            let (x, y, z) = (i % 3, i / 3 % 2, i / 6);
+            (x as f32 + 0.5) + 2. * (y as f32 + 0.5) + 4. * (z as f32 + 0.5)
+        }).collect();
+        let grid = VoxelGrid::new(3, 2, 2, values, AABB::new(Vec3::zero(), Vec3::new(3., 2., 2.)));
+        let linear = |p: Vec3| p.x() + 2. * p.y() + 4. * p.z();
+
+        // a linear field is reproduced exactly between the voxel centers
+        for &p in &[Vec3::new(0.5, 0.5, 0.5), Vec3::new(2.5, 1.5, 1.5), Vec3::new(1.2, 0.9, 1.3), Vec3::new(2.1, 1.4, 0.6)] {
+            assert!((grid.sample(&p) - linear(p)).abs() < 1e-5, "{:?}", p);
+        }
+        // half a voxel past the last center the grid blends with zero
+        let edge = Vec3::new(3., 1.5, 1.5);
+        assert!((grid.sample(&edge) - 0.5 * linear(Vec3::new(2.5, 1.5, 1.5))).abs() < 1e-5);
+        assert_eq!(grid.sample(&Vec3::new(-1., 1., 1.)), 0.);
+    }
+}